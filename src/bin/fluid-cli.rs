@@ -0,0 +1,359 @@
+// headless runner: steps the solver without a window or renderer and writes periodic outputs
+use std::{fs, path::PathBuf, process, time::Instant};
+
+use fluid_simulation::{
+    export::{self, ExportFormat},
+    fluid_world::FluidWorld,
+    frame_recording::{self, FrameFormat, FrameRecorder},
+    obstacles::ObstacleOutlines,
+    particles_spawning,
+    scenario::Scenario,
+    settings_file::Settings,
+    sim_config::SimConfig,
+    time_step,
+};
+
+const USAGE: &str = "usage: fluid-cli [options]
+
+options:
+  --scenario <file>      scenario to start from (defaults to the settings spawning)
+  --settings <file>      settings file with physics, spawning and container values
+  --steps <n>            physics steps to run (default 1000)
+  --output-every <n>     write outputs every n steps, 0 disables them (default 100)
+  --output-dir <dir>     where outputs are written (default output)
+  --format <list>        comma separated snapshot, csv, vtk, jsonl (default snapshot)
+  --frames <png|gif>     also render a frame with every output, settings [recording] by default
+  --frame-size <WxH>     size of the rendered frames, settings [recording] by default
+  --dt <seconds>         time step, defaults to physics_step from the settings,
+                         the largest one when adaptive_time_step is on
+  --help                 print this message";
+
+struct CliArgs {
+    scenario: Option<PathBuf>,
+    settings: Option<PathBuf>,
+    steps: u64,
+    output_every: u64,
+    output_dir: PathBuf,
+    formats: Vec<ExportFormat>,
+    frames: Option<FrameFormat>,
+    frame_size: Option<(u32, u32)>,
+    delta: Option<f32>,
+}
+
+impl CliArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<CliArgs, String> {
+        let mut cli_args = CliArgs {
+            scenario: None,
+            settings: None,
+            steps: 1000,
+            output_every: 100,
+            output_dir: PathBuf::from("output"),
+            formats: vec![ExportFormat::Snapshot],
+            frames: None,
+            frame_size: None,
+            delta: None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--scenario" => cli_args.scenario = Some(PathBuf::from(value()?)),
+                "--settings" => cli_args.settings = Some(PathBuf::from(value()?)),
+                "--steps" => cli_args.steps = parse_number(&arg, &value()?)?,
+                "--output-every" => cli_args.output_every = parse_number(&arg, &value()?)?,
+                "--output-dir" => cli_args.output_dir = PathBuf::from(value()?),
+                "--format" => cli_args.formats = parse_formats(&value()?)?,
+                "--frames" => {
+                    let value = value()?;
+                    cli_args.frames = Some(
+                        FrameFormat::parse(&value)
+                            .ok_or_else(|| format!("unknown frame format {}", value))?,
+                    );
+                }
+                "--frame-size" => cli_args.frame_size = Some(parse_size(&arg, &value()?)?),
+                "--dt" => cli_args.delta = Some(parse_number(&arg, &value()?)?),
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        // same rule as physics_step in the settings, the solvers divide by it
+        if cli_args
+            .delta
+            .is_some_and(|delta| !delta.is_finite() || delta <= 0f32)
+        {
+            return Err("--dt must be finite and greater than 0".to_string());
+        }
+        // frames are only rendered together with the outputs
+        if cli_args.output_every == 0
+            && (cli_args.frames.is_some() || cli_args.frame_size.is_some())
+        {
+            return Err("--frames and --frame-size need --output-every above 0".to_string());
+        }
+        Ok(cli_args)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, arg))
+}
+
+fn parse_formats(value: &str) -> Result<Vec<ExportFormat>, String> {
+    value
+        .split(',')
+        .map(|name| {
+            ExportFormat::parse(name.trim()).ok_or_else(|| format!("unknown format {}", name))
+        })
+        .collect()
+}
+
+fn parse_size(arg: &str, value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("invalid value {} for {}, expected WxH", value, arg))?;
+    let size = (parse_number(arg, width)?, parse_number(arg, height)?);
+    if size.0 == 0 || size.1 == 0 {
+        return Err(format!("{} needs a width and height greater than 0", arg));
+    }
+    Ok(size)
+}
+
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn create_world(cli_args: &CliArgs, settings: &Settings, config: &SimConfig) -> FluidWorld {
+    match &cli_args.scenario {
+        Some(path) => {
+            let scenario = Scenario::load(path).unwrap_or_else(|err| exit_with_error(err));
+            particles_spawning::create_fluid_world_from_scenario(&scenario, config)
+        }
+        None => particles_spawning::create_fluid_world(&settings.spawning, config),
+    }
+}
+
+struct Outputs {
+    recorder: Option<FrameRecorder>,
+    frame_size: (u32, u32),
+    obstacle_outlines: ObstacleOutlines,
+}
+
+impl Outputs {
+    // process::exit skips destructors and the gif trailer is only written when the
+    // recorder is dropped, so the frames recorded so far stay readable
+    fn exit_with_error(&mut self, message: impl std::fmt::Display) -> ! {
+        self.recorder = None;
+        exit_with_error(message)
+    }
+}
+
+fn write_outputs(
+    world: &FluidWorld,
+    config: &SimConfig,
+    cli_args: &CliArgs,
+    outputs: &mut Outputs,
+) {
+    for format in &cli_args.formats {
+        if let Err(err) = export::export_world(world, *format, &cli_args.output_dir) {
+            outputs.exit_with_error(format!("can't write {:?} output: {}", format, err));
+        }
+    }
+    if let Some(recorder) = &mut outputs.recorder {
+        let (width, height) = outputs.frame_size;
+        let image = frame_recording::render_frame(
+            world,
+            config,
+            &mut outputs.obstacle_outlines,
+            width,
+            height,
+        );
+        if let Err(err) = recorder.add_frame(image) {
+            outputs.exit_with_error(format!("can't write frame: {}", err));
+        }
+    }
+}
+
+fn main() {
+    let cli_args = CliArgs::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let settings = match &cli_args.settings {
+        Some(path) => Settings::load(path).unwrap_or_else(|err| exit_with_error(err)),
+        None => Settings::default(),
+    };
+    let config = settings.sim_config();
+    let delta = cli_args.delta.unwrap_or(config.physics_step);
+    // the settings only check physics_step, a particle can't take more than the whole
+    // difference to the wall in one step of --dt either
+    if config.heat.wall_heat_transfer * delta > 1f32 {
+        exit_with_error(format!(
+            "--dt {} is too large for physics.heat.wall_heat_transfer {}, must not be above {}",
+            delta,
+            config.heat.wall_heat_transfer,
+            1f32 / config.heat.wall_heat_transfer
+        ));
+    }
+
+    let mut world = create_world(&cli_args, &settings, &config);
+    println!(
+        "running {} steps of {} particles with dt {}",
+        cli_args.steps,
+        world.len(),
+        delta
+    );
+
+    let mut outputs = Outputs {
+        recorder: None,
+        frame_size: cli_args
+            .frame_size
+            .unwrap_or((settings.recording.width, settings.recording.height)),
+        obstacle_outlines: ObstacleOutlines::default(),
+    };
+    if cli_args.output_every > 0 {
+        if let Err(err) = fs::create_dir_all(&cli_args.output_dir) {
+            exit_with_error(format!(
+                "can't create {}: {}",
+                cli_args.output_dir.display(),
+                err
+            ));
+        }
+        if let Some(format) = cli_args.frames.or(settings.recording.format) {
+            let recorder = FrameRecorder::create(
+                format,
+                &cli_args.output_dir,
+                settings.recording.gif_frame_delay_ms,
+            )
+            .unwrap_or_else(|err| exit_with_error(format!("can't start recording: {}", err)));
+            outputs.recorder = Some(recorder);
+        }
+        write_outputs(&world, &config, &cli_args, &mut outputs);
+    }
+
+    let start = Instant::now();
+    for step in 1..=cli_args.steps {
+        let step_delta = if config.adaptive_time_step {
+            time_step::adaptive_time_step(&world, &config).min(delta)
+        } else {
+            delta
+        };
+        world.step(&config, step_delta);
+
+        if cli_args.output_every > 0 && step % cli_args.output_every == 0 {
+            write_outputs(&world, &config, &cli_args, &mut outputs);
+            println!(
+                "step {}/{} ({:.1}s), energy {}, pressure iterations {}, density error {:.2}%, \
+                 divergence iterations {}, divergence error {:.2}%",
+                step,
+                cli_args.steps,
+                start.elapsed().as_secs_f32(),
+                world.mechanical_energy(&config),
+                world.solver_stats.iterations,
+                world.solver_stats.density_error * 100f32,
+                world.solver_stats.divergence_iterations,
+                world.solver_stats.divergence_error * 100f32
+            );
+        }
+    }
+
+    println!(
+        "done in {:.1}s, {} simulated seconds, {} particles, state hash {:016x}",
+        start.elapsed().as_secs_f32(),
+        world.time,
+        world.len(),
+        world.state_hash()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_defaults() {
+        let cli_args = parse(&[]).unwrap();
+        assert_eq!(cli_args.scenario, None);
+        assert_eq!(cli_args.steps, 1000);
+        assert_eq!(cli_args.output_every, 100);
+        assert_eq!(cli_args.output_dir, PathBuf::from("output"));
+        assert_eq!(cli_args.formats, vec![ExportFormat::Snapshot]);
+        assert_eq!(cli_args.frames, None);
+        assert_eq!(cli_args.frame_size, None);
+        assert_eq!(cli_args.delta, None);
+    }
+
+    #[test]
+    fn parses_values() {
+        let cli_args = parse(&[
+            "--scenario",
+            "scenarios/dam_break.toml",
+            "--steps",
+            "50",
+            "--output-every",
+            "10",
+            "--output-dir",
+            "out",
+            "--format",
+            "csv, jsonl",
+            "--frames",
+            "gif",
+            "--frame-size",
+            "320x240",
+            "--dt",
+            "0.005",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli_args.scenario,
+            Some(PathBuf::from("scenarios/dam_break.toml"))
+        );
+        assert_eq!(cli_args.steps, 50);
+        assert_eq!(cli_args.output_every, 10);
+        assert_eq!(cli_args.output_dir, PathBuf::from("out"));
+        assert_eq!(
+            cli_args.formats,
+            vec![ExportFormat::Csv, ExportFormat::JsonLines]
+        );
+        assert_eq!(cli_args.frames, Some(FrameFormat::Gif));
+        assert_eq!(cli_args.frame_size, Some((320, 240)));
+        assert_eq!(cli_args.delta, Some(0.005f32));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert_eq!(
+            parse(&["--dt", "0"]).err().as_deref(),
+            Some("--dt must be finite and greater than 0")
+        );
+        assert!(parse(&["--dt", "NaN"]).is_err());
+        assert!(parse(&["--dt", "inf"]).is_err());
+        assert_eq!(
+            parse(&["--output-every", "0", "--frames", "png"])
+                .err()
+                .as_deref(),
+            Some("--frames and --frame-size need --output-every above 0")
+        );
+        assert!(parse(&["--output-every", "0", "--frame-size", "320x240"]).is_err());
+        assert!(parse(&["--frame-size", "0x0"]).is_err());
+        assert!(parse(&["--frame-size", "320"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["--steps"]).is_err());
+        assert_eq!(
+            parse(&["--fast"]).err().as_deref(),
+            Some("unknown argument --fast")
+        );
+    }
+}
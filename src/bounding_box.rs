@@ -1,13 +1,22 @@
 use bevy::{color::palettes::css::GREY, math::*, prelude::*, sprite::Sprite};
 
+use crate::sim_config::SimConfig;
+
 pub const BOX_BOUNDS_SIZE_PIXELS: Vec2 = Vec2::new(1700f32, 1000f32);
 const BOX_SPRITE_PATH: &str = "sprites/box.png";
 pub const BOX_SPRITE_RESOLUTION: Vec2 = Vec2::new(50f32, 50f32);
 
-pub fn spawn_bounding_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+#[derive(Component)]
+pub struct BoundingBox;
+
+pub fn spawn_bounding_box(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<SimConfig>,
+) {
     let mut sprite = Sprite::from_image(asset_server.load(BOX_SPRITE_PATH));
     sprite.color = Color::Srgba(GREY);
-    let scale = BOX_BOUNDS_SIZE_PIXELS / BOX_SPRITE_RESOLUTION;
+    let scale = config.box_size / BOX_SPRITE_RESOLUTION;
 
     // Z works like layer so -1000 to make it stay in the background
     let transform = Transform {
@@ -16,5 +25,20 @@ pub fn spawn_bounding_box(mut commands: Commands, asset_server: Res<AssetServer>
         ..default()
     };
 
-    commands.spawn((transform, sprite));
+    commands.spawn((transform, sprite, BoundingBox));
+}
+
+// container size can change when the settings file is reloaded
+pub fn update_bounding_box(
+    config: Res<SimConfig>,
+    mut bounding_box: Query<&mut Transform, With<BoundingBox>>,
+) {
+    if !config.is_changed() {
+        return;
+    }
+
+    let scale = config.box_size / BOX_SPRITE_RESOLUTION;
+    for mut transform in &mut bounding_box {
+        transform.scale = Vec3::new(scale.x, scale.y, 1f32);
+    }
 }
@@ -0,0 +1,227 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{fluid_world::FluidWorld, settings_file, snapshot};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Snapshot,
+    Csv,
+    Vtk,
+    #[serde(alias = "jsonl")]
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        settings_file::parse_name(name)
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Snapshot => "fsnap",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Vtk => "vtk",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+// [export] section of the settings file, nothing is written while `formats` is empty
+#[derive(Resource, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    pub formats: Vec<ExportFormat>,
+    // physics steps between two exports
+    pub interval: u64,
+    pub output_dir: PathBuf,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            formats: Vec::new(),
+            interval: 10,
+            output_dir: PathBuf::from("output"),
+        }
+    }
+}
+
+// writes `particles_<step>.<extension>` into `output_dir`
+pub fn export_world(
+    world: &FluidWorld,
+    format: ExportFormat,
+    output_dir: &Path,
+) -> io::Result<PathBuf> {
+    let path = output_dir.join(format!(
+        "particles_{:08}.{}",
+        world.steps,
+        format.extension()
+    ));
+    let mut writer = BufWriter::new(fs::File::create(&path)?);
+    match format {
+        ExportFormat::Snapshot => snapshot::write_snapshot(world, &mut writer)?,
+        ExportFormat::Csv => write_csv(world, &mut writer)?,
+        ExportFormat::Vtk => write_vtk(world, &mut writer)?,
+        ExportFormat::JsonLines => write_json_lines(world, &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(path)
+}
+
+// pressure of the last step, missing for a world that was never stepped
+fn pressure(world: &FluidWorld, index: usize) -> Option<f32> {
+    world.pressures.get(index).copied()
+}
+
+// the pressure cell is left empty when the world has none yet
+pub fn write_csv(world: &FluidWorld, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "id,x,y,vx,vy,density,pressure,fluid,temperature")?;
+    for i in 0..world.len() {
+        let (pos, velocity, density, fluid) = (
+            world.positions[i],
+            world.velocities[i],
+            world.densities[i],
+            world.fluid_kinds[i],
+        );
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            i,
+            pos.x,
+            pos.y,
+            velocity.x,
+            velocity.y,
+            density,
+            pressure(world, i).map_or(String::new(), |pressure| pressure.to_string()),
+            fluid.index(),
+            world.temperatures[i]
+        )?;
+    }
+    Ok(())
+}
+
+// legacy VTK poly data point cloud, opens directly in ParaView. vtk has no missing values,
+// the pressure array is left out when the world has none yet
+pub fn write_vtk(world: &FluidWorld, writer: &mut impl Write) -> io::Result<()> {
+    let count = world.len();
+    writeln!(writer, "# vtk DataFile Version 3.0")?;
+    writeln!(
+        writer,
+        "fluid particles step {} time {}",
+        world.steps, world.time
+    )?;
+    writeln!(writer, "ASCII")?;
+    writeln!(writer, "DATASET POLYDATA")?;
+    writeln!(writer, "POINTS {} float", count)?;
+    for pos in &world.positions {
+        writeln!(writer, "{} {} 0", pos.x, pos.y)?;
+    }
+    writeln!(writer, "VERTICES {} {}", count, count * 2)?;
+    for i in 0..count {
+        writeln!(writer, "1 {}", i)?;
+    }
+
+    writeln!(writer, "POINT_DATA {}", count)?;
+    writeln!(writer, "VECTORS velocity float")?;
+    for velocity in &world.velocities {
+        writeln!(writer, "{} {} 0", velocity.x, velocity.y)?;
+    }
+    writeln!(writer, "SCALARS density float 1")?;
+    writeln!(writer, "LOOKUP_TABLE default")?;
+    for density in &world.densities {
+        writeln!(writer, "{}", density)?;
+    }
+    if world.pressures.len() == count {
+        writeln!(writer, "SCALARS pressure float 1")?;
+        writeln!(writer, "LOOKUP_TABLE default")?;
+        for pressure in &world.pressures {
+            writeln!(writer, "{}", pressure)?;
+        }
+    }
+    writeln!(writer, "SCALARS fluid int 1")?;
+    writeln!(writer, "LOOKUP_TABLE default")?;
+    for fluid in &world.fluid_kinds {
+        writeln!(writer, "{}", fluid.index())?;
+    }
+    writeln!(writer, "SCALARS temperature float 1")?;
+    writeln!(writer, "LOOKUP_TABLE default")?;
+    for temperature in &world.temperatures {
+        writeln!(writer, "{}", temperature)?;
+    }
+    Ok(())
+}
+
+// one json object per particle and line, pressure is null when the world has none yet
+pub fn write_json_lines(world: &FluidWorld, writer: &mut impl Write) -> io::Result<()> {
+    for i in 0..world.len() {
+        let (pos, velocity, density, fluid) = (
+            world.positions[i],
+            world.velocities[i],
+            world.densities[i],
+            world.fluid_kinds[i],
+        );
+        writeln!(
+            writer,
+            "{{\"step\":{},\"time\":{},\"id\":{},\"x\":{},\"y\":{},\"vx\":{},\"vy\":{},\"density\":{},\"pressure\":{},\"fluid\":{},\"temperature\":{}}}",
+            world.steps,
+            json_number(world.time),
+            i,
+            json_number(pos.x),
+            json_number(pos.y),
+            json_number(velocity.x),
+            json_number(velocity.y),
+            json_number(density),
+            json_number(pressure(world, i).unwrap_or(f32::NAN)),
+            fluid.index(),
+            json_number(world.temperatures[i])
+        )?;
+    }
+    Ok(())
+}
+
+// json has no NaN or infinity
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+pub fn export_particles(
+    world: Res<FluidWorld>,
+    export_config: Res<ExportConfig>,
+    mut last_exported_step: Local<Option<u64>>,
+) {
+    if export_config.formats.is_empty() {
+        return;
+    }
+    // several physics steps run every frame, export once the interval is crossed
+    let interval_index = world.steps / export_config.interval;
+    if last_exported_step.is_some_and(|last| last / export_config.interval == interval_index) {
+        return;
+    }
+    *last_exported_step = Some(world.steps);
+
+    if let Err(err) = fs::create_dir_all(&export_config.output_dir) {
+        error!(
+            "can't create {}: {}",
+            export_config.output_dir.display(),
+            err
+        );
+        return;
+    }
+    for format in &export_config.formats {
+        if let Err(err) = export_world(&world, *format, &export_config.output_dir) {
+            error!("can't export particles as {:?}: {}", format, err);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fs,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    color::palettes::css::{GREY, LIGHT_GRAY, ORANGE},
+    math::*,
+    prelude::*,
+};
+use image::{
+    Delay, Frame, ImageFormat, Rgba, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fluid_world::FluidWorld,
+    obstacles::ObstacleOutlines,
+    particles_spawning::{PARTICLE_RAY, PARTICLE_RESOLUTION},
+    particles_visuals, settings_file,
+    sim_config::SimConfig,
+};
+
+// same as the default clear color of the app window
+const BACKGROUND_COLOR: Rgba<u8> = Rgba([43, 44, 47, 255]);
+// keeps the container outline inside the image
+const FRAME_MARGIN: f32 = 1.04f32;
+// 1 is the best quality, 30 the fastest quantization
+const GIF_ENCODING_SPEED: i32 = 10;
+const GIF_FILE_NAME: &str = "recording.gif";
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameFormat {
+    Png,
+    Gif,
+}
+
+impl FrameFormat {
+    pub fn parse(name: &str) -> Option<FrameFormat> {
+        settings_file::parse_name(name)
+    }
+}
+
+// [recording] section of the settings file, nothing is rendered while `format` is not set
+#[derive(Resource, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    pub format: Option<FrameFormat>,
+    pub width: u32,
+    pub height: u32,
+    // physics steps between two frames
+    pub interval: u64,
+    pub output_dir: PathBuf,
+    pub gif_frame_delay_ms: u32,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            format: None,
+            width: 850,
+            height: 500,
+            interval: 10,
+            output_dir: PathBuf::from("frames"),
+            gif_frame_delay_ms: 40,
+        }
+    }
+}
+
+// draws the container outline, every particle colored by fluid and speed, the obstacles and
+// the rigid bodies, like the app does. `obstacle_outlines` lives as long as the recording
+pub fn render_frame(
+    world: &FluidWorld,
+    config: &SimConfig,
+    obstacle_outlines: &mut ObstacleOutlines,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(width, height, BACKGROUND_COLOR);
+    let image_size = vec2(width as f32, height as f32);
+    let scale = (image_size / (config.box_size * FRAME_MARGIN)).min_element();
+    // world origin is the center of the container and y goes up
+    let to_pixel = |pos: Vec2| vec2(pos.x, -pos.y) * scale + image_size / 2f32;
+
+    draw_rect_outline(
+        &mut image,
+        to_pixel(-config.box_size / 2f32),
+        to_pixel(config.box_size / 2f32),
+        to_rgba(GREY),
+    );
+
+    let radius = (PARTICLE_RAY * PARTICLE_RESOLUTION / 2f32 * scale).max(0.5f32);
+    for i in 0..world.len() {
+        let color = to_rgba(particles_visuals::particle_color(
+            world.velocities[i],
+            world.fluid_kinds[i],
+            world.temperatures[i],
+            world.is_frozen(i),
+            config,
+        ));
+        draw_disc(&mut image, to_pixel(world.positions[i]), radius, color);
+    }
+    for (start, end) in obstacle_outlines.segments(&world.obstacles, config.box_size) {
+        draw_line(
+            &mut image,
+            to_pixel(*start),
+            to_pixel(*end),
+            to_rgba(LIGHT_GRAY),
+        );
+    }
+    for body in &world.bodies {
+        let outline: Vec<Vec2> = body.outline().into_iter().map(to_pixel).collect();
+        for line in outline.windows(2) {
+            draw_line(&mut image, line[0], line[1], to_rgba(ORANGE));
+        }
+    }
+    image
+}
+
+fn to_rgba(color: Srgba) -> Rgba<u8> {
+    Rgba(color.to_u8_array())
+}
+
+fn draw_disc(image: &mut RgbaImage, center: Vec2, radius: f32, color: Rgba<u8>) {
+    if !center.is_finite() {
+        return;
+    }
+    let min = (center - radius).floor().max(Vec2::ZERO);
+    let max = (center + radius)
+        .ceil()
+        .min(vec2(image.width() as f32, image.height() as f32));
+    for y in min.y as u32..max.y as u32 {
+        for x in min.x as u32..max.x as u32 {
+            let pixel_center = vec2(x as f32, y as f32) + 0.5f32;
+            if pixel_center.distance_squared(center) <= radius * radius {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+fn draw_rect_outline(image: &mut RgbaImage, corner_a: Vec2, corner_b: Vec2, color: Rgba<u8>) {
+    let last_pixel = vec2(image.width() as f32, image.height() as f32) - 1f32;
+    let min = corner_a.min(corner_b).round().clamp(Vec2::ZERO, last_pixel);
+    let max = corner_a.max(corner_b).round().clamp(Vec2::ZERO, last_pixel);
+    let (min_x, min_y, max_x, max_y) = (min.x as u32, min.y as u32, max.x as u32, max.y as u32);
+    for x in min_x..=max_x {
+        image.put_pixel(x, min_y, color);
+        image.put_pixel(x, max_y, color);
+    }
+    for y in min_y..=max_y {
+        image.put_pixel(min_x, y, color);
+        image.put_pixel(max_x, y, color);
+    }
+}
+
+fn draw_line(image: &mut RgbaImage, start: Vec2, end: Vec2, color: Rgba<u8>) {
+    let steps = start.distance(end).ceil().max(1f32) as u32;
+    for step in 0..=steps {
+        let point = start.lerp(end, step as f32 / steps as f32).round();
+        if point.x >= 0f32
+            && point.y >= 0f32
+            && point.x < image.width() as f32
+            && point.y < image.height() as f32
+        {
+            image.put_pixel(point.x as u32, point.y as u32, color);
+        }
+    }
+}
+
+// numbered png files or a single animated gif in `output_dir`
+pub enum FrameRecorder {
+    Png {
+        output_dir: PathBuf,
+        frames: u32,
+    },
+    Gif {
+        encoder: GifEncoder<BufWriter<fs::File>>,
+        delay: Delay,
+    },
+}
+
+impl FrameRecorder {
+    pub fn create(
+        format: FrameFormat,
+        output_dir: &Path,
+        gif_frame_delay_ms: u32,
+    ) -> io::Result<FrameRecorder> {
+        fs::create_dir_all(output_dir)?;
+        match format {
+            FrameFormat::Png => Ok(FrameRecorder::Png {
+                output_dir: output_dir.to_owned(),
+                frames: 0,
+            }),
+            FrameFormat::Gif => {
+                let file = fs::File::create(output_dir.join(GIF_FILE_NAME))?;
+                let mut encoder =
+                    GifEncoder::new_with_speed(BufWriter::new(file), GIF_ENCODING_SPEED);
+                encoder
+                    .set_repeat(Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                Ok(FrameRecorder::Gif {
+                    encoder,
+                    delay: Delay::from_numer_denom_ms(gif_frame_delay_ms, 1),
+                })
+            }
+        }
+    }
+
+    pub fn add_frame(&mut self, image: RgbaImage) -> io::Result<()> {
+        match self {
+            FrameRecorder::Png { output_dir, frames } => {
+                let path = output_dir.join(format!("frame_{:05}.png", frames));
+                image
+                    .save_with_format(&path, ImageFormat::Png)
+                    .map_err(io::Error::other)?;
+                *frames += 1;
+            }
+            FrameRecorder::Gif { encoder, delay } => encoder
+                .encode_frame(Frame::from_parts(image, 0, 0, *delay))
+                .map_err(io::Error::other)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ActiveRecording {
+    recorder: Option<FrameRecorder>,
+    last_recorded_step: Option<u64>,
+    obstacle_outlines: ObstacleOutlines,
+}
+
+pub fn record_frames(
+    world: Res<FluidWorld>,
+    config: Res<SimConfig>,
+    recording_config: Res<RecordingConfig>,
+    mut recording: ResMut<ActiveRecording>,
+) {
+    // (re)starts the recording when the settings change, this also runs on the first frame
+    if recording_config.is_changed() {
+        // dropping the recorder writes the gif trailer
+        recording.recorder = None;
+        recording.last_recorded_step = None;
+        recording.recorder = recording_config.format.and_then(|format| {
+            FrameRecorder::create(
+                format,
+                &recording_config.output_dir,
+                recording_config.gif_frame_delay_ms,
+            )
+            .inspect_err(|err| {
+                error!(
+                    "can't start recording into {}: {}",
+                    recording_config.output_dir.display(),
+                    err
+                )
+            })
+            .ok()
+        });
+    }
+
+    // several physics steps run every frame, render once the interval is crossed
+    let interval_index = world.steps / recording_config.interval;
+    if recording
+        .last_recorded_step
+        .is_some_and(|last| last / recording_config.interval == interval_index)
+    {
+        return;
+    }
+    // borrows the recorder and the outlines at the same time
+    let recording = &mut *recording;
+    let Some(recorder) = &mut recording.recorder else {
+        return;
+    };
+    let image = render_frame(
+        &world,
+        &config,
+        &mut recording.obstacle_outlines,
+        recording_config.width,
+        recording_config.height,
+    );
+    if let Err(err) = recorder.add_frame(image) {
+        error!("can't record frame: {}", err);
+        recording.recorder = None;
+        return;
+    }
+    recording.last_recorded_step = Some(world.steps);
+}
+
+pub fn finish_recording_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut recording: ResMut<ActiveRecording>,
+) {
+    if exit_events.read().next().is_some() {
+        recording.recorder = None;
+    }
+}
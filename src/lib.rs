@@ -0,0 +1,53 @@
+pub mod bounding_box;
+#[path = "physics/collisions.rs"]
+pub mod collisions;
+#[path = "physics/dfsph.rs"]
+pub mod dfsph;
+#[path = "physics/equation_of_state.rs"]
+pub mod equation_of_state;
+pub mod export;
+#[path = "physics/flip.rs"]
+pub mod flip;
+#[path = "physics/fluid_kind.rs"]
+pub mod fluid_kind;
+#[path = "physics/fluid_world.rs"]
+pub mod fluid_world;
+pub mod frame_recording;
+#[path = "physics/heat.rs"]
+pub mod heat;
+#[path = "physics/integrators.rs"]
+pub mod integrators;
+#[path = "physics/obstacles.rs"]
+pub mod obstacles;
+pub mod particle_grid;
+#[path = "physics/particle_physics.rs"]
+pub mod particle_physics;
+pub mod particles_spawning;
+pub mod particles_visuals;
+#[path = "physics/pbf.rs"]
+pub mod pbf;
+#[path = "physics/pcisph.rs"]
+pub mod pcisph;
+#[path = "physics/phase_change.rs"]
+pub mod phase_change;
+#[path = "physics/player_interaction_physics.rs"]
+pub mod player_interaction_physics;
+#[path = "physics/pressure_handler.rs"]
+pub mod pressure_handler;
+#[path = "physics/rigid_body.rs"]
+pub mod rigid_body;
+pub mod scenario;
+pub mod settings_file;
+#[path = "physics/sim_config.rs"]
+pub mod sim_config;
+#[path = "physics/smoothing_kernels.rs"]
+pub mod smoothing_kernels;
+pub mod snapshot;
+pub mod solid_visuals;
+#[path = "physics/surface_tension.rs"]
+pub mod surface_tension;
+#[path = "physics/time_step.rs"]
+pub mod time_step;
+pub mod ui_handler;
+#[path = "physics/viscosity_force.rs"]
+pub mod viscosity_force;
@@ -1,66 +1,101 @@
-mod bounding_box;
-#[path = "physics/collisions.rs"]
-mod collisions;
-mod particle_grid;
-#[path = "physics/particle_physics.rs"]
-mod particle_physics;
-mod particles_spawning;
-mod particles_visuals;
-#[path = "physics/player_interaction_physics.rs"]
-mod player_interaction_physics;
-#[path = "physics/pressure_handler.rs"]
-mod pressure_handler;
-mod ui_handler;
-#[path = "physics/viscosity_force.rs"]
-mod viscosity_force;
-
 use bevy::{
     color::palettes::css::{BLUE, GREEN, RED},
     core::TaskPoolThreadAssignmentPolicy,
     math::vec2,
     prelude::*,
-    render::pipelined_rendering::PipelinedRenderingPlugin,
     tasks::available_parallelism,
 };
-use particle_grid::{GRID_SIZE_X, GRID_SIZE_Y};
-use particle_physics::Particle;
-use pressure_handler::SMOOTHING_DISTANCE;
+use fluid_simulation::{
+    bounding_box, export,
+    fluid_world::FluidWorld,
+    frame_recording::{self, ActiveRecording},
+    particle_grid,
+    particle_physics::{self, PhysicsClock},
+    particles_spawning::{self, SpawnConfig},
+    particles_visuals,
+    settings_file::{self, Settings, SettingsFileWatcher},
+    sim_config::SimConfig,
+    snapshot, solid_visuals, ui_handler,
+};
+use std::path::PathBuf;
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(TaskPoolPlugin {
-            task_pool_options: TaskPoolOptions {
-                compute: TaskPoolThreadAssignmentPolicy {
-                    // set the minimum # of compute threads
-                    // to the total number of available threads
-                    min_threads: available_parallelism(),
-                    max_threads: usize::MAX, // unlimited max threads
-                    percent: 1.0,            // this value is irrelevant in this case
-                },
-                // keep the defaults for everything else
-                ..default()
+    // optional settings file as the first argument, e.g. `cargo run -- settings.toml`
+    let settings_path = std::env::args().nth(1).map(PathBuf::from);
+    let settings = match &settings_path {
+        Some(path) => match Settings::load(path) {
+            Ok(settings) => settings,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        None => Settings::default(),
+    };
+    let watcher = settings_path.map(|path| SettingsFileWatcher::new(path, &settings.spawning));
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(TaskPoolPlugin {
+        task_pool_options: TaskPoolOptions {
+            compute: TaskPoolThreadAssignmentPolicy {
+                // set the minimum # of compute threads
+                // to the total number of available threads
+                min_threads: available_parallelism(),
+                max_threads: usize::MAX, // unlimited max threads
+                percent: 1.0,            // this value is irrelevant in this case
             },
-        }))
-        .add_systems(Startup, (setup, bounding_box::spawn_bounding_box))
-        .add_systems(
-            Update,
-            (
-                particle_physics::handle_particles_physics,
-                ui_handler::update_ui,
-                debug_input_update,
-                particles_visuals::update_particles_visuals,
-            ),
-        )
-        .run();
+            // keep the defaults for everything else
+            ..default()
+        },
+    }))
+    .insert_resource(settings.sim_config())
+    .insert_resource(settings.spawning)
+    .insert_resource(settings.export)
+    .insert_resource(settings.recording)
+    .init_resource::<ActiveRecording>()
+    .init_resource::<PhysicsClock>()
+    .add_systems(Startup, (setup, bounding_box::spawn_bounding_box))
+    .add_systems(
+        Update,
+        (
+            settings_file::hot_reload_settings,
+            particle_physics::handle_particles_physics,
+            particles_spawning::spawn_emitted_particles,
+            snapshot::handle_snapshot_keys,
+            export::export_particles.after(particle_physics::handle_particles_physics),
+            frame_recording::record_frames.after(particle_physics::handle_particles_physics),
+            ui_handler::update_ui,
+            debug_input_update,
+            particles_visuals::update_particles_visuals,
+            solid_visuals::draw_rigid_bodies,
+            solid_visuals::draw_obstacles,
+            bounding_box::update_bounding_box,
+        ),
+    )
+    .add_systems(Last, frame_recording::finish_recording_on_exit);
+    if let Some(watcher) = watcher {
+        app.insert_resource(watcher);
+    }
+    app.run();
 }
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    spawn_config: Res<SpawnConfig>,
+    config: Res<SimConfig>,
+) {
     commands.spawn(Camera2d);
     commands.spawn(DebugPointer {
         pos: Vec2::new(1f32, 1f32),
     });
 
     ui_handler::setup_ui(&mut commands);
-    particles_spawning::handle_spawning_particles(&mut commands, &asset_server);
+    particles_spawning::handle_spawning_particles(
+        &mut commands,
+        &asset_server,
+        &spawn_config,
+        &config,
+    );
 }
 const USE_DEBUG_POINTER: bool = false;
 const DEBUG_POINTER_MOVEMENT_SPEED: f32 = 30f32;
@@ -68,7 +103,8 @@ const DEBUG_CHECKED_PARTICLES: bool = true;
 
 fn debug_input_update(
     mut pointer_query: Query<&mut DebugPointer>,
-    particles: Query<(&mut Transform, &mut Particle), With<Particle>>,
+    world: Res<FluidWorld>,
+    config: Res<SimConfig>,
     keys: Res<ButtonInput<KeyCode>>,
     mut gizmos: Gizmos,
 ) {
@@ -94,37 +130,28 @@ fn debug_input_update(
 
     let pointer_isometry = Isometry2d::new(pointer.pos, Rot2::degrees(0f32));
     gizmos.circle_2d(pointer_isometry, 10f32, RED);
-    gizmos.circle_2d(pointer_isometry, SMOOTHING_DISTANCE as f32, BLUE);
+    gizmos.circle_2d(pointer_isometry, config.smoothing_distance, BLUE);
 
-    if DEBUG_CHECKED_PARTICLES {
-        let mut particle_predicted_positions =
-            Vec::with_capacity(particles_spawning::PARTICLES_COUNT as usize);
-        for (_, particle) in &particles {
-            particle_predicted_positions.push(particle.predicted_position);
-        }
-        let grid = particle_grid::split_particles_into_grid(&particle_predicted_positions);
-        let connected_pos =
-            particle_grid::get_connected_cells(&particle_grid::pixel_pos_to_gird_pos(&pointer.pos));
+    // grid is only built by the first physics step
+    if DEBUG_CHECKED_PARTICLES && !world.grid.is_empty() {
+        let particle_predicted_positions = &world.predicted_positions;
+        let grid = &world.grid;
+        let pointer_grid_pos = particle_grid::pixel_pos_to_gird_pos(&pointer.pos, &config);
+        let connected_pos = particle_grid::get_connected_cells(&pointer_grid_pos, &config);
 
         for cell_pos in connected_pos {
-            let pixel_pos = (cell_pos - vec2(GRID_SIZE_X / 2f32, GRID_SIZE_Y / 2f32))
-                * SMOOTHING_DISTANCE as f32;
+            let pixel_pos = (cell_pos - config.grid_size() / 2f32) * config.smoothing_distance;
             let iso = Isometry2d::new(pixel_pos, Rot2::degrees(0f32));
-            gizmos.rect_2d(
-                iso,
-                vec2(SMOOTHING_DISTANCE as f32, SMOOTHING_DISTANCE as f32),
-                GREEN,
-            );
+            gizmos.rect_2d(iso, Vec2::splat(config.smoothing_distance), GREEN);
         }
-        let connected_indexes = particle_grid::get_connected_cells_indexes(
-            &particle_grid::pixel_pos_to_gird_pos(&pointer.pos),
-        );
+        let connected_indexes =
+            particle_grid::get_connected_cells_indexes(&pointer_grid_pos, &config);
         // println!(
         //     "&particle_grid::pixel_pos_to_gird_pos(&pointer.pos) {}",
         //     &particle_grid::pixel_pos_to_gird_pos(&pointer.pos)
         // );
         for cell_index in connected_indexes {
-            if cell_index == usize::MAX {
+            if cell_index == usize::MAX || cell_index >= grid.len() {
                 continue;
             }
 
@@ -1,30 +1,200 @@
-use crate::{bounding_box::BOX_BOUNDS_SIZE_PIXELS, particle_physics};
+use crate::{
+    collisions,
+    fluid_kind::FluidKind,
+    fluid_world::{self, FluidWorld},
+    particle_physics::{self, Particle},
+    phase_change,
+    rigid_body::RigidBody,
+    scenario::Scenario,
+    sim_config::SimConfig,
+};
 use bevy::{math::vec2, prelude::*};
-use rand::{Rng, rngs::ThreadRng};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 const CIRCLE_SPRITE_PATH: &str = "sprites/circle.png";
 pub const PARTICLE_RAY: f32 = 0.03f32;
+pub const PARTICLES_SPACING: f32 = 3f32;
 pub const PARTICLE_RESOLUTION: f32 = 50f32;
 pub const STANDARD_PARTICLE_MASS: f32 = 2f32;
+const SPAWN_VELOCITY: Vec2 = vec2(1f32, 0f32);
 
-pub fn handle_spawning_particles(commands: &mut Commands, asset_server: &Res<AssetServer>) {
-    let mut rng = rand::rng();
+// how many particles already have an entity, updated together with the world resource
+#[derive(Resource, Default)]
+pub struct SpawnedParticles(pub usize);
 
-    for i in 0..PARTICLES_COUNT {
+#[derive(Resource, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnConfig {
+    pub particles_count: u32,
+    pub particles_layers: u32,
+    pub particles_spacing: f32,
+    // scatters the particles over the whole container instead of stacking them in a block,
+    // positions come from `physics.seed` when running deterministic
+    pub random_positions: bool,
+    pub fluid: FluidKind,
+    // replaces the default block of particles, relative paths start at the settings file
+    #[serde(rename = "scenario")]
+    pub scenario_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub scenario: Option<Scenario>,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        SpawnConfig {
+            particles_count: 100000,
+            particles_layers: 200,
+            particles_spacing: PARTICLES_SPACING,
+            random_positions: false,
+            fluid: FluidKind::Water,
+            scenario_path: None,
+            scenario: None,
+        }
+    }
+}
+
+pub fn handle_spawning_particles(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    spawn_config: &SpawnConfig,
+    config: &SimConfig,
+) {
+    spawn_world(
+        commands,
+        asset_server,
+        create_fluid_world(spawn_config, config),
+    );
+}
+// spawns an entity for every particle and makes `world` the simulated one
+pub fn spawn_world(commands: &mut Commands, asset_server: &Res<AssetServer>, world: FluidWorld) {
+    spawn_particles_entities(0..world.len(), &world, commands, asset_server);
+    commands.insert_resource(SpawnedParticles(world.len()));
+    commands.insert_resource(world);
+}
+// emitters keep adding particles to the world, they need entities to be visible
+pub fn spawn_emitted_particles(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    world: Res<FluidWorld>,
+    mut spawned: ResMut<SpawnedParticles>,
+) {
+    if world.len() > spawned.0 {
+        spawn_particles_entities(spawned.0..world.len(), &world, &mut commands, &asset_server);
+        spawned.0 = world.len();
+    }
+}
+fn spawn_particles_entities(
+    indexes: std::ops::Range<usize>,
+    world: &FluidWorld,
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+) {
+    for index in indexes {
         spawn_particle(
-            STANDARD_PARTICLE_MASS,
             PARTICLE_RAY,
-            get_particle_spawn_position(i as f32, &mut rng),
-            i as usize,
+            world.positions[index],
+            world.velocities[index],
+            world.fluid_kinds[index],
+            index,
             commands,
             asset_server,
         );
     }
 }
+// throws away every particle entity and replaces the simulated world
+pub fn respawn_particles(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    particles: &Query<Entity, With<Particle>>,
+    world: FluidWorld,
+) {
+    for entity in particles {
+        commands.entity(entity).despawn();
+    }
+    spawn_world(commands, asset_server, world);
+}
+// builds the initial particle state without touching the ECS
+pub fn create_fluid_world(spawn_config: &SpawnConfig, config: &SimConfig) -> FluidWorld {
+    match &spawn_config.scenario {
+        Some(scenario) => create_fluid_world_from_scenario(scenario, config),
+        None => create_block_fluid_world(spawn_config, config),
+    }
+}
+fn create_block_fluid_world(spawn_config: &SpawnConfig, config: &SimConfig) -> FluidWorld {
+    let mut rng = if config.deterministic {
+        StdRng::seed_from_u64(config.seed)
+    } else {
+        StdRng::from_os_rng()
+    };
+    let mut world = FluidWorld::with_capacity(spawn_config.particles_count as usize);
+    let mass = config.fluids.get(spawn_config.fluid).mass();
+    let temperature = config.heat.reference_temperature;
+
+    for i in 0..spawn_config.particles_count {
+        let index = world.add_particle(
+            get_particle_spawn_position(i as f32, spawn_config, config, &mut rng),
+            SPAWN_VELOCITY,
+            mass,
+            fluid_world::particle_area(PARTICLE_RAY),
+            spawn_config.fluid,
+            temperature,
+        );
+        world.liquid_fractions[index] = phase_change::initial_liquid_fraction(temperature, config);
+    }
+    world
+}
+pub fn create_fluid_world_from_scenario(scenario: &Scenario, config: &SimConfig) -> FluidWorld {
+    let mut world = FluidWorld {
+        bodies: scenario
+            .body
+            .iter()
+            .map(|body| RigidBody::from_scenario(body, config))
+            .collect(),
+        ..Default::default()
+    };
+    for region in &scenario.fluid {
+        let mass = region
+            .mass
+            .unwrap_or_else(|| config.fluids.get(region.fluid).mass());
+        let temperature = region
+            .temperature
+            .unwrap_or(config.heat.reference_temperature);
+        // the fluid fills up around the bodies, particles squeezed out of them would start
+        // the fluid compressed
+        for position in region.particle_positions(config.particle_spacing, config.box_size) {
+            if world
+                .bodies
+                .iter()
+                .any(|body| body.covers(position, config))
+            {
+                continue;
+            }
+            let index = world.add_particle(
+                position,
+                region.velocity,
+                mass,
+                fluid_world::particle_area(PARTICLE_RAY),
+                region.fluid,
+                temperature,
+            );
+            world.liquid_fractions[index] =
+                phase_change::initial_liquid_fraction(temperature, config);
+        }
+    }
+    world.walls = scenario.wall.clone();
+    world.obstacles = scenario.obstacle.clone();
+    for emitter in &scenario.emitter {
+        world.add_emitter(emitter.clone());
+    }
+    world
+}
 fn spawn_particle(
-    mass: f32,
     ray: f32,
     pos: Vec2,
+    velocity: Vec2,
+    fluid: FluidKind,
     index: usize,
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
@@ -36,35 +206,41 @@ fn spawn_particle(
         scale: Vec3::new(ray, ray, ray),
         ..default()
     };
-    let particle = particle_physics::Particle::new(mass, vec2(1f32, 0f32), PARTICLE_RAY, index);
+    let particle = particle_physics::Particle::new(velocity, index, fluid);
 
     commands.spawn((particle, transform, sprite));
 }
 
-pub const PARTICLES_COUNT: u32 = 100000;
-const PARTICLES_LAYERS: u32 = 200;
-const PARTICLES_SPACING: f32 = 3f32;
-
-const PARTICLES_SIZE_ASPECT: f32 = PARTICLES_COUNT as f32 / PARTICLES_LAYERS as f32;
-const OFFSET_VEC: Vec2 = vec2(
-    -PARTICLES_SPACING * PARTICLES_SIZE_ASPECT / 2f32,
-    -(PARTICLES_LAYERS as f32 / 2f32) * PARTICLES_SPACING,
-);
-
-fn get_particle_spawn_position(index: f32, rng: &mut ThreadRng) -> Vec2 {
-    get_box_spawn_point(index)
-    //get_random_spawn_point(rng)
+fn get_particle_spawn_position(
+    index: f32,
+    spawn_config: &SpawnConfig,
+    config: &SimConfig,
+    rng: &mut StdRng,
+) -> Vec2 {
+    if spawn_config.random_positions {
+        get_random_spawn_point(config, rng)
+    } else {
+        get_box_spawn_point(index, spawn_config)
+    }
 }
-fn get_random_spawn_point(rng: &mut ThreadRng) -> Vec2 {
-    let real_box_size = BOX_BOUNDS_SIZE_PIXELS / 2f32;
-    let y = rng.random_range(-(real_box_size.y) as i32..(real_box_size.y) as i32);
-    let x = rng.random_range(-(real_box_size.x) as i32..(real_box_size.x) as i32);
-
-    vec2(x as f32, y as f32)
+// anywhere particle centers can be inside the container
+fn get_random_spawn_point(config: &SimConfig, rng: &mut StdRng) -> Vec2 {
+    let half_size = collisions::container_half_size(config);
+    vec2(
+        rng.random_range(-half_size.x..=half_size.x),
+        rng.random_range(-half_size.y..=half_size.y),
+    )
 }
-fn get_box_spawn_point(index: f32) -> Vec2 {
-    let y = (index / PARTICLES_SIZE_ASPECT).floor();
-    let x = index - y * PARTICLES_SIZE_ASPECT;
+fn get_box_spawn_point(index: f32, spawn_config: &SpawnConfig) -> Vec2 {
+    let particles_size_aspect =
+        spawn_config.particles_count as f32 / spawn_config.particles_layers as f32;
+    let offset = vec2(
+        -spawn_config.particles_spacing * particles_size_aspect / 2f32,
+        -(spawn_config.particles_layers as f32 / 2f32) * spawn_config.particles_spacing,
+    );
+
+    let y = (index / particles_size_aspect).floor();
+    let x = index - y * particles_size_aspect;
 
-    vec2(x, y) * PARTICLES_SPACING + OFFSET_VEC
+    vec2(x, y) * spawn_config.particles_spacing + offset
 }
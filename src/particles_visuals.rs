@@ -1,15 +1,22 @@
 use bevy::{
-    color::palettes::css::{DARK_BLUE, LIGHT_GREEN},
+    color::palettes::css::{BLUE, LIGHT_CYAN, LIGHT_GREEN, RED},
     math::{VectorSpace, vec3},
     prelude::*,
     sprite::Sprite,
 };
 
-use crate::{particle_physics::Particle, particles_spawning::PARTICLE_RAY};
+use crate::{
+    fluid_kind::FluidKind, particle_physics::Particle, particles_spawning::PARTICLE_RAY,
+    sim_config::SimConfig,
+};
 const SHOW_PARTICLE_VISUALS: bool = true;
 const SPEED_VISUALIZATION_SCALE: f32 = 80f32;
+const ICE_COLOR: Srgba = LIGHT_CYAN;
 
-pub fn update_particles_visuals(mut particles: Query<(&mut Transform, &Particle, &mut Sprite)>) {
+pub fn update_particles_visuals(
+    mut particles: Query<(&mut Transform, &Particle, &mut Sprite)>,
+    config: Res<SimConfig>,
+) {
     if !SHOW_PARTICLE_VISUALS {
         return;
     }
@@ -17,11 +24,37 @@ pub fn update_particles_visuals(mut particles: Query<(&mut Transform, &Particle,
     particles
         .par_iter_mut()
         .for_each(|(mut transform, particle, mut sprite)| {
-            let t = particle.velocity.length() / SPEED_VISUALIZATION_SCALE;
-            sprite.color = Color::Srgba(Srgba::lerp(DARK_BLUE, LIGHT_GREEN, t));
+            sprite.color = Color::Srgba(particle_color(
+                particle.velocity,
+                particle.fluid,
+                particle.temperature,
+                particle.frozen,
+                &config,
+            ));
 
             let scale = PARTICLE_RAY
                 /* * (pressure_handler::TARGET_DENSITY / particle.density).clamp(0.1f32, 3f32) */;
             transform.scale = vec3(scale, scale, 0f32);
         });
 }
+
+// slow particles have the color of their fluid and get greener the faster they move,
+// unless [physics.heat] color_range asks for cold blue to hot red. ice is always pale blue
+pub fn particle_color(
+    velocity: Vec2,
+    fluid: FluidKind,
+    temperature: f32,
+    frozen: bool,
+    config: &SimConfig,
+) -> Srgba {
+    if frozen {
+        return ICE_COLOR;
+    }
+    if let Some(range) = config.heat.color_range {
+        let t = ((temperature - range.x) / (range.y - range.x)).clamp(0f32, 1f32);
+        return Srgba::lerp(BLUE, RED, t);
+    }
+    let color = config.fluids.get(fluid).color;
+    let t = velocity.length() / SPEED_VISUALIZATION_SCALE;
+    Srgba::lerp(Srgba::rgb(color.x, color.y, color.z), LIGHT_GREEN, t)
+}
@@ -1,18 +1,100 @@
-use bevy::{math::Vec2, prelude::Transform};
-
-const COLLISION_DAMPING: f32 = 0.5f32;
-use crate::{bounding_box, particle_physics::Particle, particles_spawning};
-pub fn resolve_collisions(particle: &mut Particle, transform: &mut Transform) {
-    let half_bauds_size = bounding_box::BOX_BOUNDS_SIZE_PIXELS / 2f32
-        - Vec2::ONE * particles_spawning::PARTICLE_RAY * particles_spawning::PARTICLE_RESOLUTION
-            / 2f32;
-
-    if transform.translation.x.abs() > half_bauds_size.x {
-        transform.translation.x = half_bauds_size.x * transform.translation.x.signum();
-        particle.velocity.x *= -1f32 * COLLISION_DAMPING;
+use bevy::math::Vec2;
+
+use crate::{obstacles::Obstacle, particles_spawning, scenario::Wall, sim_config::SimConfig};
+
+pub fn particle_radius() -> f32 {
+    particles_spawning::PARTICLE_RAY * particles_spawning::PARTICLE_RESOLUTION / 2f32
+}
+
+// half size of the area particle centers are kept in
+pub fn container_half_size(config: &SimConfig) -> Vec2 {
+    config.box_size / 2f32 - Vec2::ONE * particle_radius()
+}
+
+// point of the segment from `start` to `end` closest to `point`
+pub fn closest_point_on_segment(point: Vec2, start: Vec2, end: Vec2) -> Vec2 {
+    let segment = end - start;
+    let t = if segment == Vec2::ZERO {
+        0f32
+    } else {
+        ((point - start).dot(segment) / segment.length_squared()).clamp(0f32, 1f32)
+    };
+    start + segment * t
+}
+
+// whether `point` is inside the polygon by the even-odd rule, so any simple polygon works
+pub fn polygon_contains(points: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[j]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+// smallest and largest corner of the box around every point
+pub fn points_bounds(points: &[Vec2]) -> (Vec2, Vec2) {
+    points.iter().fold(
+        (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+        |(min, max), point| (min.min(*point), max.max(*point)),
+    )
+}
+
+pub fn resolve_collisions(
+    position: &mut Vec2,
+    velocity: &mut Vec2,
+    walls: &[Wall],
+    obstacles: &[Obstacle],
+    config: &SimConfig,
+) {
+    for wall in walls {
+        resolve_wall_collision(position, velocity, wall, config);
+    }
+    for obstacle in obstacles {
+        obstacle.resolve_collision(position, velocity);
+    }
+
+    let half_bauds_size = container_half_size(config);
+
+    if position.x.abs() > half_bauds_size.x {
+        position.x = half_bauds_size.x * position.x.signum();
+        velocity.x *= -config.collision_damping;
     }
-    if transform.translation.y.abs() > half_bauds_size.y {
-        transform.translation.y = half_bauds_size.y * transform.translation.y.signum();
-        particle.velocity.y *= -1f32 * COLLISION_DAMPING;
+    if position.y.abs() > half_bauds_size.y {
+        position.y = half_bauds_size.y * position.y.signum();
+        velocity.y *= -config.collision_damping;
+    }
+}
+
+fn resolve_wall_collision(
+    position: &mut Vec2,
+    velocity: &mut Vec2,
+    wall: &Wall,
+    config: &SimConfig,
+) {
+    let closest = closest_point_on_segment(*position, wall.start, wall.end);
+    let offset = *position - closest;
+    let min_distance = wall.thickness / 2f32 + particle_radius();
+    let distance = offset.length();
+    if distance >= min_distance {
+        return;
+    }
+
+    // particle exactly on the wall line, push it out to the left side of the wall
+    let normal = if distance == 0f32 {
+        (wall.end - wall.start).perp().normalize_or(Vec2::Y)
+    } else {
+        offset / distance
+    };
+    *position = closest + normal * min_distance;
+    let normal_speed = velocity.dot(normal);
+    if normal_speed < 0f32 {
+        *velocity -= normal * normal_speed * (1f32 + config.collision_damping);
     }
 }
@@ -0,0 +1,212 @@
+use bevy::math::*;
+
+use crate::{
+    fluid_world::{FluidWorld, SolverStats, par_map_particles},
+    pcisph::{gradient_sums, pushing_neighbour_positions, sample_density},
+    pressure_handler::INFLUENCE_MODIFIER,
+    rigid_body,
+    sim_config::SimConfig,
+};
+
+// same reason as in pcisph, every particle corrects itself assuming its neighbours don't
+const RELAXATION: f32 = 0.3;
+
+// what the velocities are corrected against
+#[derive(Clone, Copy)]
+enum Correction {
+    // density after moving one step with the current velocities
+    Density,
+    // rate the current velocities compress the fluid at
+    Divergence,
+}
+
+// divergence-free SPH (Bender and Koschier 2015): works on velocities only, first removes
+// the compression left in the velocities of the last step, then corrects the velocities
+// after the external forces until moving with them keeps the density constant.
+// neighbours and densities come from the grid built at the start of the step
+pub fn step(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    world.update_neighbourhoods(world.positions.clone(), config);
+    let particles_count = world.len();
+    let densities = par_map_particles(particles_count, |i| {
+        sample_density(world, &world.positions, i, config)
+    });
+    let stiffness_factors =
+        par_map_particles(particles_count, |i| stiffness_factor(world, i, config));
+    let external = par_map_particles(particles_count, |i| world.external_acceleration(i, config));
+    let start_velocities = world.velocities.clone();
+    // both corrections add up what the bodies pushed every particle with
+    world.body_pressure_terms = vec![0f32; particles_count];
+
+    let (divergence_iterations, divergence_error) = correct_velocities(
+        world,
+        config,
+        delta,
+        &densities,
+        &stiffness_factors,
+        Correction::Divergence,
+    );
+    for (velocity, acceleration) in world.velocities.iter_mut().zip(&external) {
+        *velocity += *acceleration * delta;
+    }
+    let (iterations, density_error) = correct_velocities(
+        world,
+        config,
+        delta,
+        &densities,
+        &stiffness_factors,
+        Correction::Density,
+    );
+
+    world.accelerations = world
+        .velocities
+        .iter()
+        .zip(&start_velocities)
+        .map(|(velocity, start_velocity)| (*velocity - *start_velocity) / delta)
+        .collect();
+    world.update_max_acceleration();
+    for (position, velocity) in world.positions.iter_mut().zip(&world.velocities) {
+        *position += *velocity * delta;
+    }
+    world.solver_stats = SolverStats {
+        iterations,
+        density_error,
+        divergence_iterations,
+        divergence_error,
+    };
+}
+
+// returns the iterations done and the largest relative error left
+fn correct_velocities(
+    world: &mut FluidWorld,
+    config: &SimConfig,
+    delta: f32,
+    densities: &[f32],
+    stiffness_factors: &[f32],
+    correction: Correction,
+) -> (u32, f32) {
+    let solve = &config.pressure_solve;
+    let max_error = match correction {
+        Correction::Density => solve.max_density_error,
+        Correction::Divergence => solve.max_divergence_error,
+    };
+    let mut iterations = 0;
+    loop {
+        // compression the velocities would cause over this step, stretching is left alone.
+        // densities are neighbour counts, every fluid is as much denser as it's heavier
+        let errors = par_map_particles(world.len(), |i| {
+            let compression = density_change_rate(world, i, config) * delta;
+            let count_error = match correction {
+                Correction::Density => densities[i] + compression - config.water_rest_density,
+                Correction::Divergence => compression,
+            };
+            count_error.max(0f32) * world.relative_density(i, config)
+        });
+        let error = errors
+            .iter()
+            .zip(&world.fluid_kinds)
+            .fold(0f32, |max, (error, fluid)| {
+                max.max(error / config.rest_density(*fluid))
+            });
+        if iterations >= solve.max_iterations
+            || (iterations >= solve.min_iterations && error <= max_error)
+        {
+            return (iterations, error);
+        }
+
+        let stiffness: Vec<f32> = errors
+            .iter()
+            .zip(stiffness_factors)
+            .map(|(error, factor)| RELAXATION * error / delta.powi(2) * factor)
+            .collect();
+        let corrections = par_map_particles(world.len(), |i| {
+            velocity_correction(world, i, &stiffness, config) * delta
+        });
+        for (velocity, correction) in world.velocities.iter_mut().zip(corrections) {
+            *velocity += correction;
+        }
+        let pressure_terms = par_map_particles(world.len(), |i| {
+            stiffness[i] * INFLUENCE_MODIFIER / world.relative_density(i, config)
+        });
+        for (sum, pressure_term) in world.body_pressure_terms.iter_mut().zip(pressure_terms) {
+            *sum += pressure_term;
+        }
+        iterations += 1;
+    }
+}
+
+// how fast the density of `index` changes with the current velocities,
+// mirrored neighbours move like the mirror image of their particle and boundary samples
+// like the body they belong to
+fn density_change_rate(world: &FluidWorld, index: usize, config: &SimConfig) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    let h = config.smoothing_distance;
+    let position = world.positions[index];
+    let velocity = world.velocities[index];
+    let fluid_rate: f32 = pushing_neighbour_positions(world, &world.positions, index, config)
+        .map(|(j, neighbour_position, flip)| {
+            let gradient = kernel.gradient(position - neighbour_position, h);
+            (velocity - world.velocities[j] * flip).dot(gradient) * INFLUENCE_MODIFIER
+        })
+        .sum();
+    let body_rate: f32 = rigid_body::boundary_neighbours(world, position, config)
+        .map(|(_, sample)| {
+            let gradient = kernel.gradient(position - sample.position, h);
+            (velocity - sample.velocity).dot(gradient) * sample.weight * INFLUENCE_MODIFIER
+        })
+        .sum();
+    fluid_rate + body_rate
+}
+
+// symmetric pressure acceleration with kappa / density as the pressure term,
+// the pairs push equally so a heavier particle is accelerated less
+fn velocity_correction(
+    world: &FluidWorld,
+    index: usize,
+    stiffness: &[f32],
+    config: &SimConfig,
+) -> Vec2 {
+    let kernel = config.kernels.pressure.kernel();
+    let position = world.positions[index];
+    let mut acceleration = Vec2::ZERO;
+    for (j, neighbour_position, _) in
+        pushing_neighbour_positions(world, &world.positions, index, config)
+    {
+        let gradient = kernel.gradient(position - neighbour_position, config.smoothing_distance);
+        acceleration -= (stiffness[index] + stiffness[j]) * gradient;
+    }
+    let pressure_term =
+        stiffness[index] * INFLUENCE_MODIFIER / world.relative_density(index, config);
+    acceleration * INFLUENCE_MODIFIER / world.relative_density(index, config)
+        + rigid_body::boundary_acceleration(world, position, pressure_term, config)
+}
+
+// the "alpha" of the paper without the density, it cancels with the one dividing kappa
+fn stiffness_factor(world: &FluidWorld, index: usize, config: &SimConfig) -> f32 {
+    let (gradient_sum, gradient_dot_sum) = gradient_sums(world, &world.positions, index, config);
+    let denominator =
+        INFLUENCE_MODIFIER.powi(2) * (gradient_sum.dot(gradient_sum) + gradient_dot_sum);
+    if denominator > 0f32 {
+        1f32 / denominator
+    } else {
+        0f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fluid_world::tests::settle_block, sim_config::SolverKind};
+
+    // a block dropped into a small container spreads out and comes to rest, the velocity
+    // corrections keep it from getting squeezed and never add energy
+    #[test]
+    fn block_settles() {
+        let block = settle_block(SolverKind::Dfsph);
+        assert!(block.peak_energy <= block.start_energy);
+        let density_error = block.world.solver_stats.density_error;
+        assert!(
+            density_error <= block.config.pressure_solve.max_density_error,
+            "{}",
+            density_error
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sim_config::SimConfig;
+
+// exponent of the Tait equation, the usual value for water
+const TAIT_GAMMA: i32 = 7;
+
+// turns a particle density into the pressure that pushes it back towards the rest density
+// of its fluid
+pub trait EquationOfState {
+    fn pressure(&self, density: f32, rest_density: f32, config: &SimConfig) -> f32;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EquationOfStateKind {
+    #[default]
+    Linear,
+    Tait,
+    IdealGas,
+}
+
+impl EquationOfStateKind {
+    pub fn equation(&self) -> &'static dyn EquationOfState {
+        match self {
+            EquationOfStateKind::Linear => &Linear,
+            EquationOfStateKind::Tait => &Tait,
+            EquationOfStateKind::IdealGas => &IdealGas,
+        }
+    }
+}
+
+// p = k (density - rest)
+pub struct Linear;
+
+impl EquationOfState for Linear {
+    fn pressure(&self, density: f32, rest_density: f32, config: &SimConfig) -> f32 {
+        (density - rest_density) * config.pressure_multiplier
+    }
+}
+
+// weakly compressible p = B ((density / rest)^7 - 1), B is picked so small
+// compressions feel the same stiffness as the linear equation and big ones much more
+pub struct Tait;
+
+impl EquationOfState for Tait {
+    fn pressure(&self, density: f32, rest_density: f32, config: &SimConfig) -> f32 {
+        let b = config.pressure_multiplier * rest_density / TAIT_GAMMA as f32;
+        b * ((density / rest_density).powi(TAIT_GAMMA) - 1f32)
+    }
+}
+
+// p = k density, never pulls particles together so the fluid behaves like a gas filling the box.
+// ignores the rest density, there's no density the fluid settles at
+pub struct IdealGas;
+
+impl EquationOfState for IdealGas {
+    fn pressure(&self, density: f32, _rest_density: f32, config: &SimConfig) -> f32 {
+        density * config.pressure_multiplier
+    }
+}
@@ -0,0 +1,528 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collisions::{closest_point_on_segment, resolve_collisions},
+    fluid_world::{FluidWorld, SolverStats, par_map_particles},
+    obstacles::Obstacle,
+    rigid_body::RigidBody,
+    scenario::Wall,
+    sim_config::SimConfig,
+};
+
+// how velocities go from the grid back to the particles
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    // blend of the interpolated grid velocity (pic, smooth) and the particle velocity
+    // plus the grid change (flip, lively but noisy), picked by `flip_ratio`
+    #[default]
+    PicFlip,
+    // affine particle-in-cell, every particle also carries its velocity gradient,
+    // keeps rotation like flip without its noise
+    Apic,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlipConfig {
+    pub transfer: TransferKind,
+    // 0 is pure pic, 1 is pure flip
+    pub flip_ratio: f32,
+    // side of a grid cell in pixels, about two particle spacings works best
+    pub cell_size: f32,
+    // successive over-relaxation of the pressure iterations, between 1 and 2
+    pub over_relaxation: f32,
+    // how much of the compression of a cell is pushed out every step, keeps the volume
+    // from drifting since the grid alone only cares about divergence
+    pub drift_correction: f32,
+}
+
+impl Default for FlipConfig {
+    fn default() -> Self {
+        FlipConfig {
+            transfer: TransferKind::PicFlip,
+            flip_ratio: 0.95f32,
+            cell_size: 6f32,
+            over_relaxation: 1.9f32,
+            drift_correction: 0.1f32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CellKind {
+    Air,
+    Fluid,
+    Solid,
+}
+
+// one velocity component on the faces of the cells, `offset` is where face (0, 0) is
+// in cell units, so u sits on the vertical faces and v on the horizontal ones
+struct FaceGrid {
+    width: usize,
+    height: usize,
+    offset: Vec2,
+    values: Vec<f32>,
+    // values from before the pressure solve, flip adds the difference to the particles
+    old_values: Vec<f32>,
+    weights: Vec<f32>,
+    // relative density of the fluid around the face, the same pressure moves heavier fluid less
+    densities: Vec<f32>,
+}
+
+impl FaceGrid {
+    fn new(width: usize, height: usize, offset: Vec2) -> FaceGrid {
+        FaceGrid {
+            width,
+            height,
+            offset,
+            values: vec![0f32; width * height],
+            old_values: vec![0f32; width * height],
+            weights: vec![0f32; width * height],
+            densities: vec![1f32; width * height],
+        }
+    }
+
+    // the 4 faces around `point` (in cell units) with their bilinear weights and the
+    // weight gradients in cell units, clamped to the grid
+    fn stencil(&self, point: Vec2) -> [(usize, Vec2, f32, Vec2); 4] {
+        let local = (point - self.offset).clamp(
+            Vec2::ZERO,
+            vec2(self.width as f32, self.height as f32) - 1.001f32,
+        );
+        let base = local.floor();
+        let fraction = local - base;
+        let (x, y) = (base.x as usize, base.y as usize);
+        let node = |dx: usize, dy: usize| {
+            let wx = if dx == 0 {
+                1f32 - fraction.x
+            } else {
+                fraction.x
+            };
+            let wy = if dy == 0 {
+                1f32 - fraction.y
+            } else {
+                fraction.y
+            };
+            let sign = vec2(
+                if dx == 0 { -1f32 } else { 1f32 },
+                if dy == 0 { -1f32 } else { 1f32 },
+            );
+            (
+                (y + dy) * self.width + x + dx,
+                vec2((x + dx) as f32, (y + dy) as f32) + self.offset,
+                wx * wy,
+                sign * vec2(wy, wx),
+            )
+        };
+        [node(0, 0), node(1, 0), node(0, 1), node(1, 1)]
+    }
+}
+
+// staggered (MAC) grid over the container, cells split it like the particle grid
+// splits it into buckets, just finer
+struct MacGrid {
+    width: usize,
+    height: usize,
+    cell_size: f32,
+    origin: Vec2,
+    u: FaceGrid,
+    v: FaceGrid,
+    kinds: Vec<CellKind>,
+    // body covering every solid cell the bodies make, its faces move with that body
+    cell_bodies: Vec<Option<usize>>,
+    // particles per cell splatted on the cell centers
+    particle_counts: Vec<f32>,
+    // pressure of every fluid cell summed over the iterations, as the velocity it gave the
+    // faces of water
+    pressures: Vec<f32>,
+}
+
+impl MacGrid {
+    fn new(config: &SimConfig) -> MacGrid {
+        let cell_size = config.flip.cell_size;
+        let size = (config.box_size / cell_size).ceil();
+        let (width, height) = (size.x as usize, size.y as usize);
+        MacGrid {
+            width,
+            height,
+            cell_size,
+            origin: -size * cell_size / 2f32,
+            u: FaceGrid::new(width + 1, height, vec2(0f32, 0.5f32)),
+            v: FaceGrid::new(width, height + 1, vec2(0.5f32, 0f32)),
+            kinds: vec![CellKind::Air; width * height],
+            cell_bodies: vec![None; width * height],
+            particle_counts: vec![0f32; width * height],
+            pressures: vec![0f32; width * height],
+        }
+    }
+
+    fn to_grid(&self, position: Vec2) -> Vec2 {
+        (position - self.origin) / self.cell_size
+    }
+
+    fn cell_center(&self, x: usize, y: usize) -> Vec2 {
+        self.origin + (vec2(x as f32, y as f32) + 0.5f32) * self.cell_size
+    }
+
+    // outside the container counts as solid
+    fn kind(&self, x: isize, y: isize) -> CellKind {
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            CellKind::Solid
+        } else {
+            self.kinds[y as usize * self.width + x as usize]
+        }
+    }
+
+    fn cell_body(&self, x: isize, y: isize) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            None
+        } else {
+            self.cell_bodies[y as usize * self.width + x as usize]
+        }
+    }
+
+    fn mark_cells(
+        &mut self,
+        positions: &[Vec2],
+        walls: &[Wall],
+        obstacles: &[Obstacle],
+        bodies: &[RigidBody],
+    ) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let center = self.cell_center(x, y);
+                let in_wall = walls.iter().any(|wall| {
+                    center.distance(closest_point_on_segment(center, wall.start, wall.end))
+                        < wall.thickness / 2f32
+                }) || obstacles
+                    .iter()
+                    .any(|obstacle| obstacle.shape.distance(center) < 0f32);
+                let body = bodies.iter().position(|body| body.contains(center));
+                self.cell_bodies[y * self.width + x] = body;
+                self.kinds[y * self.width + x] = if in_wall || body.is_some() {
+                    CellKind::Solid
+                } else {
+                    CellKind::Air
+                };
+            }
+        }
+        for position in positions {
+            let cell = self.to_grid(*position).floor();
+            let (x, y) = (cell.x as isize, cell.y as isize);
+            if self.kind(x, y) == CellKind::Air {
+                self.kinds[y as usize * self.width + x as usize] = CellKind::Fluid;
+            }
+        }
+
+        let centers = FaceGrid::new(self.width, self.height, Vec2::splat(0.5f32));
+        self.particle_counts.fill(0f32);
+        for position in positions {
+            for (index, _, weight, _) in centers.stencil(self.to_grid(*position)) {
+                self.particle_counts[index] += weight;
+            }
+        }
+    }
+
+    // velocity of a face between `cells` at `face` (in cell units) touching a solid, the one
+    // of the body a cell belongs to, every other solid holds it still
+    fn solid_face_velocity(
+        &self,
+        cells: [(isize, isize); 2],
+        face: Vec2,
+        bodies: &[RigidBody],
+    ) -> Vec2 {
+        cells
+            .iter()
+            .find_map(|(x, y)| self.cell_body(*x, *y))
+            .map_or(Vec2::ZERO, |body| {
+                bodies[body].point_velocity(self.origin + face * self.cell_size)
+            })
+    }
+
+    // faces touching a solid cell move with it
+    fn set_solid_faces(&mut self, bodies: &[RigidBody]) {
+        for y in 0..self.height {
+            for x in 0..=self.width {
+                let cells = [(x as isize - 1, y as isize), (x as isize, y as isize)];
+                if cells
+                    .iter()
+                    .any(|(x, y)| self.kind(*x, *y) == CellKind::Solid)
+                {
+                    let face = vec2(x as f32, y as f32) + self.u.offset;
+                    let velocity = self.solid_face_velocity(cells, face, bodies);
+                    self.u.values[y * self.u.width + x] = velocity.x;
+                }
+            }
+        }
+        for y in 0..=self.height {
+            for x in 0..self.width {
+                let cells = [(x as isize, y as isize - 1), (x as isize, y as isize)];
+                if cells
+                    .iter()
+                    .any(|(x, y)| self.kind(*x, *y) == CellKind::Solid)
+                {
+                    let face = vec2(x as f32, y as f32) + self.v.offset;
+                    let velocity = self.solid_face_velocity(cells, face, bodies);
+                    self.v.values[y * self.v.width + x] = velocity.y;
+                }
+            }
+        }
+    }
+
+    // impulse every fluid cell pushed the bodies next to it with over the solve, at the
+    // face between them. the summed pressure is the velocity it gave a face of water,
+    // so the impulse is that times the water a cell holds
+    fn body_impulses(&self, config: &SimConfig) -> Vec<(usize, Vec2, Vec2)> {
+        let cell_mass =
+            config.fluids.water.mass() * (self.cell_size / config.particle_spacing).powi(2);
+        let mut impulses = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pressure = self.pressures[y * self.width + x];
+                if self.kinds[y * self.width + x] != CellKind::Fluid || pressure <= 0f32 {
+                    continue;
+                }
+                for direction in [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y] {
+                    let (nx, ny) = (
+                        x as isize + direction.x as isize,
+                        y as isize + direction.y as isize,
+                    );
+                    if let Some(body) = self.cell_body(nx, ny) {
+                        let face = self.cell_center(x, y) + direction * self.cell_size / 2f32;
+                        impulses.push((body, direction * pressure * cell_mass, face));
+                    }
+                }
+            }
+        }
+        impulses
+    }
+
+    // gauss-seidel on the divergence of every fluid cell, pressure is applied straight to
+    // the faces over their density. returns the iterations done and the largest
+    // compression per step left
+    fn solve_pressure(&mut self, config: &SimConfig, delta: f32) -> (u32, f32) {
+        let solve = &config.pressure_solve;
+        // particles a cell holds at rest
+        let rest_count = (self.cell_size / config.particle_spacing).powi(2);
+        self.pressures.fill(0f32);
+        let mut iterations = 0;
+        loop {
+            let mut max_error = 0f32;
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    if self.kinds[y * self.width + x] != CellKind::Fluid {
+                        continue;
+                    }
+                    let (xi, yi) = (x as isize, y as isize);
+                    let (u_left, u_right) = (y * self.u.width + x, y * self.u.width + x + 1);
+                    let (v_bottom, v_top) = (y * self.v.width + x, (y + 1) * self.v.width + x);
+                    let open = |kind: CellKind| if kind == CellKind::Solid { 0f32 } else { 1f32 };
+                    let left = open(self.kind(xi - 1, yi)) / self.u.densities[u_left];
+                    let right = open(self.kind(xi + 1, yi)) / self.u.densities[u_right];
+                    let bottom = open(self.kind(xi, yi - 1)) / self.v.densities[v_bottom];
+                    let top = open(self.kind(xi, yi + 1)) / self.v.densities[v_top];
+                    let open_sides = left + right + bottom + top;
+                    if open_sides == 0f32 {
+                        continue;
+                    }
+
+                    let mut divergence = self.u.values[u_right] - self.u.values[u_left]
+                        + self.v.values[v_top]
+                        - self.v.values[v_bottom];
+                    let compression =
+                        (self.particle_counts[y * self.width + x] / rest_count - 1f32).max(0f32);
+                    divergence -=
+                        config.flip.drift_correction * compression * self.cell_size / delta;
+                    max_error = max_error.max(divergence.abs() * delta / self.cell_size);
+
+                    let pressure = -divergence / open_sides * config.flip.over_relaxation;
+                    self.pressures[y * self.width + x] += pressure;
+                    self.u.values[u_left] -= left * pressure;
+                    self.u.values[u_right] += right * pressure;
+                    self.v.values[v_bottom] -= bottom * pressure;
+                    self.v.values[v_top] += top * pressure;
+                }
+            }
+            iterations += 1;
+            if iterations >= solve.max_iterations
+                || (iterations >= solve.min_iterations && max_error <= solve.max_divergence_error)
+            {
+                return (iterations, max_error);
+            }
+        }
+    }
+
+    // a face only has a meaningful velocity next to fluid
+    fn face_is_valid(&self, horizontal: bool, index: usize) -> bool {
+        let (x, y, before) = if horizontal {
+            let (x, y) = (index % self.u.width, index / self.u.width);
+            (x as isize, y as isize, (x as isize - 1, y as isize))
+        } else {
+            let (x, y) = (index % self.v.width, index / self.v.width);
+            (x as isize, y as isize, (x as isize, y as isize - 1))
+        };
+        self.kind(x, y) == CellKind::Fluid || self.kind(before.0, before.1) == CellKind::Fluid
+    }
+}
+
+// FLIP / APIC (Zhu and Bridson 2005, Jiang et al. 2015): particles carry the fluid,
+// their velocities are moved to a MAC grid, made divergence free there and moved back.
+// no neighbour sums in the pressure solve, so it handles many particles and large steps.
+// there are no particle pairs inside the ice to skip either, holding the clusters rigid
+// after the step only keeps how the grid moved and turned them as a whole
+pub fn step(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    // densities and the neighbour grid are still needed for viscosity, colours and exports
+    world.update_neighbourhoods(world.positions.clone(), config);
+    let particles_count = world.len();
+    let external = par_map_particles(particles_count, |i| world.external_acceleration(i, config));
+    let start_velocities = world.velocities.clone();
+    for (velocity, acceleration) in world.velocities.iter_mut().zip(&external) {
+        *velocity += *acceleration * delta;
+    }
+
+    let apic = config.flip.transfer == TransferKind::Apic;
+    let mut grid = MacGrid::new(config);
+    grid.mark_cells(
+        &world.positions,
+        &world.walls,
+        &world.obstacles,
+        &world.bodies,
+    );
+    particles_to_grid(&mut grid, world, config, apic);
+    grid.set_solid_faces(&world.bodies);
+    grid.u.old_values.clone_from(&grid.u.values);
+    grid.v.old_values.clone_from(&grid.v.values);
+    let (iterations, divergence_error) = grid.solve_pressure(config, delta);
+    grid_to_particles(&grid, world, config, apic);
+    let mut body_impulses = vec![(Vec2::ZERO, 0f32); world.bodies.len()];
+    for (body, impulse, point) in grid.body_impulses(config) {
+        let arm = point - world.bodies[body].position;
+        body_impulses[body].0 += impulse;
+        body_impulses[body].1 += arm.perp_dot(impulse);
+    }
+    for (body, (impulse, angular_impulse)) in world.bodies.iter_mut().zip(body_impulses) {
+        body.apply_fluid_impulse(impulse, angular_impulse, config.gravity, delta);
+    }
+
+    world.accelerations = world
+        .velocities
+        .iter()
+        .zip(&start_velocities)
+        .map(|(velocity, start_velocity)| (*velocity - *start_velocity) / delta)
+        .collect();
+    world.update_max_acceleration();
+    for i in 0..particles_count {
+        world.positions[i] += world.velocities[i] * delta;
+        resolve_collisions(
+            &mut world.positions[i],
+            &mut world.velocities[i],
+            &world.walls,
+            &world.obstacles,
+            config,
+        );
+    }
+    world.solver_stats = SolverStats {
+        divergence_iterations: iterations,
+        divergence_error,
+        ..Default::default()
+    };
+}
+
+fn particles_to_grid(grid: &mut MacGrid, world: &FluidWorld, config: &SimConfig, apic: bool) {
+    for (component, faces) in [&mut grid.u, &mut grid.v].into_iter().enumerate() {
+        faces.values.fill(0f32);
+        faces.weights.fill(0f32);
+        faces.densities.fill(0f32);
+        for i in 0..world.len() {
+            let point = (world.positions[i] - grid.origin) / grid.cell_size;
+            let velocity = world.velocities[i][component];
+            for (index, node, weight, _) in faces.stencil(point) {
+                let affine = if apic {
+                    world.affine_velocities[i].row(component).dot(node - point) * grid.cell_size
+                } else {
+                    0f32
+                };
+                faces.values[index] += weight * (velocity + affine);
+                faces.weights[index] += weight;
+                faces.densities[index] += weight * world.relative_density(i, config);
+            }
+        }
+        let averaged = faces.values.iter_mut().zip(&mut faces.densities);
+        for ((value, density), weight) in averaged.zip(&faces.weights) {
+            if *weight > 0f32 {
+                *value /= weight;
+                *density /= weight;
+            } else {
+                *density = 1f32;
+            }
+        }
+    }
+}
+
+fn grid_to_particles(grid: &MacGrid, world: &mut FluidWorld, config: &SimConfig, apic: bool) {
+    let flip_ratio = config.flip.flip_ratio;
+    let positions = &world.positions;
+    let velocities = &world.velocities;
+    let affine_velocities = &world.affine_velocities;
+    let transferred = par_map_particles(world.len(), |i| {
+        let point = (positions[i] - grid.origin) / grid.cell_size;
+        let mut velocity = velocities[i];
+        let mut affine_rows = [affine_velocities[i].row(0), affine_velocities[i].row(1)];
+        for (component, faces) in [&grid.u, &grid.v].into_iter().enumerate() {
+            let mut total_weight = 0f32;
+            let mut pic = 0f32;
+            let mut change = 0f32;
+            let stencil = faces
+                .stencil(point)
+                .map(|node| (grid.face_is_valid(component == 0, node.0), node));
+            for (_, (index, _, weight, _)) in stencil.iter().filter(|(valid, _)| *valid) {
+                total_weight += weight;
+                pic += weight * faces.values[*index];
+                change += weight * (faces.values[*index] - faces.old_values[*index]);
+            }
+            if total_weight == 0f32 {
+                continue;
+            }
+            let (pic, change) = (pic / total_weight, change / total_weight);
+            if apic {
+                // missing faces count as the interpolated value, otherwise the weight
+                // gradients stop summing to 0 at the surface and show a made up shear
+                let gradient: Vec2 = stencil
+                    .iter()
+                    .filter(|(valid, _)| *valid)
+                    .map(|(_, (index, _, _, weight_gradient))| {
+                        *weight_gradient * (faces.values[*index] - pic)
+                    })
+                    .sum();
+                velocity[component] = pic;
+                affine_rows[component] = gradient / grid.cell_size;
+            } else {
+                velocity[component] =
+                    (1f32 - flip_ratio) * pic + flip_ratio * (velocity[component] + change);
+            }
+        }
+        (
+            velocity,
+            Mat2::from_cols(affine_rows[0], affine_rows[1]).transpose(),
+        )
+    });
+    for (i, (velocity, affine)) in transferred.into_iter().enumerate() {
+        world.velocities[i] = velocity;
+        world.affine_velocities[i] = affine;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fluid_world::tests::settle_block, sim_config::SolverKind};
+
+    // a block dropped into a small container spreads out and comes to rest, the grid
+    // pressure never adds energy
+    #[test]
+    fn block_settles() {
+        let block = settle_block(SolverKind::Flip);
+        assert!(block.peak_energy <= block.start_energy);
+    }
+}
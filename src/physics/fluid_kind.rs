@@ -0,0 +1,100 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::particles_spawning::STANDARD_PARTICLE_MASS;
+
+// which fluid a particle belongs to, every one has its own properties in [physics.fluids]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FluidKind {
+    #[default]
+    Water,
+    Oil,
+    Honey,
+}
+
+impl FluidKind {
+    pub const ALL: [FluidKind; 3] = [FluidKind::Water, FluidKind::Oil, FluidKind::Honey];
+
+    // stable number of the fluid for files
+    pub fn index(&self) -> u32 {
+        match self {
+            FluidKind::Water => 0,
+            FluidKind::Oil => 1,
+            FluidKind::Honey => 2,
+        }
+    }
+
+    pub fn from_index(index: u32) -> Option<FluidKind> {
+        FluidKind::ALL.get(index as usize).copied()
+    }
+
+    // same as in the settings and scenario files
+    pub fn name(&self) -> &'static str {
+        match self {
+            FluidKind::Water => "water",
+            FluidKind::Oil => "oil",
+            FluidKind::Honey => "honey",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FluidProperties {
+    // rest density as a multiple of `water_rest_density`. all particles take up the same
+    // room, so the particle mass scales with it too
+    pub relative_density: f32,
+    // multiplies `viscosity_strength`
+    pub relative_viscosity: f32,
+    // rgb from 0 to 1 of slow particles, fast ones still turn green
+    pub color: Vec3,
+}
+
+impl FluidProperties {
+    pub fn mass(&self) -> f32 {
+        STANDARD_PARTICLE_MASS * self.relative_density
+    }
+}
+
+// [physics.fluids] section, a fluid that is given needs all of its keys
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FluidTable {
+    pub water: FluidProperties,
+    pub oil: FluidProperties,
+    pub honey: FluidProperties,
+}
+
+impl Default for FluidTable {
+    fn default() -> Self {
+        FluidTable {
+            water: FluidProperties {
+                relative_density: 1f32,
+                relative_viscosity: 1f32,
+                // css dark blue, the color particles always had
+                color: vec3(0f32, 0f32, 0.545f32),
+            },
+            oil: FluidProperties {
+                relative_density: 0.8f32,
+                relative_viscosity: 3f32,
+                color: vec3(0.85f32, 0.65f32, 0.1f32),
+            },
+            honey: FluidProperties {
+                relative_density: 1.4f32,
+                relative_viscosity: 15f32,
+                color: vec3(0.8f32, 0.4f32, 0.05f32),
+            },
+        }
+    }
+}
+
+impl FluidTable {
+    pub fn get(&self, kind: FluidKind) -> &FluidProperties {
+        match kind {
+            FluidKind::Water => &self.water,
+            FluidKind::Oil => &self.oil,
+            FluidKind::Honey => &self.honey,
+        }
+    }
+}
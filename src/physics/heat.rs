@@ -0,0 +1,137 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collisions::container_half_size,
+    fluid_world::{FluidWorld, par_map_particles},
+    pressure_handler::INFLUENCE_MODIFIER,
+    sim_config::SimConfig,
+};
+
+// keeps the conduction term finite for particles sitting on top of each other
+const CONDUCTION_EPSILON: f32 = 0.01f32;
+
+// [physics.heat] section, with the defaults temperature never changes anything
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeatConfig {
+    // temperature particles start at and buoyancy is measured from
+    pub reference_temperature: f32,
+    // how fast heat spreads between neighbours, in px^2/s
+    pub diffusivity: f32,
+    // boussinesq buoyancy, relative density change per degree, warm fluid rises
+    pub thermal_expansion: f32,
+    // temperatures the container sides hold, missing ones don't exchange heat
+    pub walls: WallTemperatures,
+    // fraction of the difference to the wall temperature a touching particle takes per second
+    pub wall_heat_transfer: f32,
+    // colors particles from blue at the first temperature to red at the second instead of by speed
+    pub color_range: Option<Vec2>,
+    // particles colder than this freeze into rigid ice and melt back above it, missing turns it off
+    pub freezing_temperature: Option<f32>,
+    // heat freezing releases and melting takes, in degrees of the particle's own temperature
+    pub latent_heat: f32,
+    // how much lighter ice is than its liquid, lifts frozen particles like thermal expansion
+    pub freezing_expansion: f32,
+}
+
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WallTemperatures {
+    pub left: Option<f32>,
+    pub right: Option<f32>,
+    pub bottom: Option<f32>,
+    pub top: Option<f32>,
+}
+
+impl Default for HeatConfig {
+    fn default() -> Self {
+        HeatConfig {
+            reference_temperature: 20f32,
+            diffusivity: 0f32,
+            thermal_expansion: 0f32,
+            walls: WallTemperatures::default(),
+            wall_heat_transfer: 2f32,
+            color_range: None,
+            freezing_temperature: None,
+            // water needs as much heat to melt as to warm up by 80 degrees
+            latent_heat: 80f32,
+            freezing_expansion: 0.09f32,
+        }
+    }
+}
+
+// lighter when warmer than the reference, so the acceleration goes against gravity.
+// ice barely expands with temperature and only gets the lift of freezing
+pub fn buoyancy_acceleration(temperature: f32, frozen: bool, config: &SimConfig) -> Vec2 {
+    if frozen {
+        return -config.gravity * config.heat.freezing_expansion;
+    }
+    -config.gravity
+        * config.heat.thermal_expansion
+        * (temperature - config.heat.reference_temperature)
+}
+
+// conduction between neighbours plus the exchange with heated or cooled container sides,
+// uses the neighbourhoods of the last update like viscosity
+pub fn conduct_heat(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    let heat = &config.heat;
+    if heat.diffusivity == 0f32 && heat.walls == WallTemperatures::default() {
+        return;
+    }
+    let changes = par_map_particles(world.len(), |i| {
+        let mut rate = 0f32;
+        if heat.diffusivity > 0f32 {
+            rate += conduction_rate(world, i, config);
+        }
+        rate + wall_exchange_rate(world.predicted_positions[i], world.temperatures[i], config)
+    });
+    for (temperature, rate) in world.temperatures.iter_mut().zip(changes) {
+        *temperature += rate * delta;
+    }
+}
+
+// Brookshaw's SPH laplacian, only needs the first kernel derivative and conserves heat
+fn conduction_rate(world: &FluidWorld, index: usize, config: &SimConfig) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    let h = config.smoothing_distance;
+    let sample_point = world.predicted_positions[index];
+    let sample_temperature = world.temperatures[index];
+    let mut rate = 0f32;
+    for j in world.neighbours(index) {
+        let offset = sample_point - world.predicted_positions[j];
+        let distance_squared = offset.length_squared();
+        if j == index || distance_squared >= h * h {
+            continue;
+        }
+        let volume = INFLUENCE_MODIFIER * config.fluids.get(world.fluid_kinds[j]).relative_density
+            / world.densities[j];
+        // offset and gradient point opposite ways, this is negative
+        let slope = offset.dot(kernel.gradient(offset, h))
+            / (distance_squared + CONDUCTION_EPSILON * h * h);
+        rate += 2f32 * volume * (sample_temperature - world.temperatures[j]) * slope;
+    }
+    rate * config.heat.diffusivity
+}
+
+// particles closer than the smoothing distance to a side with a temperature drift towards it
+fn wall_exchange_rate(position: Vec2, temperature: f32, config: &SimConfig) -> f32 {
+    let walls = &config.heat.walls;
+    let half_size = container_half_size(config);
+    let h = config.smoothing_distance;
+    [
+        (walls.left, position.x + half_size.x),
+        (walls.right, half_size.x - position.x),
+        (walls.bottom, position.y + half_size.y),
+        (walls.top, half_size.y - position.y),
+    ]
+    .into_iter()
+    .filter_map(|(wall_temperature, distance)| {
+        wall_temperature
+            .filter(|_| distance < h)
+            .map(|wall_temperature| {
+                (wall_temperature - temperature) * config.heat.wall_heat_transfer
+            })
+    })
+    .sum()
+}
@@ -0,0 +1,126 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fluid_world::{FluidWorld, par_map_particles},
+    sim_config::SimConfig,
+};
+
+// advances positions and velocities of every particle by `delta`,
+// forces come from `FluidWorld::evaluate_accelerations`
+pub trait Integrator {
+    fn step(&self, world: &mut FluidWorld, config: &SimConfig, delta: f32);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegratorKind {
+    #[default]
+    SymplecticEuler,
+    Leapfrog,
+    VelocityVerlet,
+    Rk2,
+}
+
+impl IntegratorKind {
+    pub fn integrator(&self) -> &'static dyn Integrator {
+        match self {
+            IntegratorKind::SymplecticEuler => &SymplecticEuler,
+            IntegratorKind::Leapfrog => &Leapfrog,
+            IntegratorKind::VelocityVerlet => &VelocityVerlet,
+            IntegratorKind::Rk2 => &Rk2,
+        }
+    }
+}
+
+// forces at the positions particles would reach with their current velocity,
+// then velocity first and position with the new velocity
+pub struct SymplecticEuler;
+
+impl Integrator for SymplecticEuler {
+    fn step(&self, world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+        let predicted = par_map_particles(world.len(), |i| {
+            world.positions[i] + world.velocities[i] * delta
+        });
+        world.evaluate_accelerations(predicted, config);
+        for i in 0..world.len() {
+            world.velocities[i] += world.accelerations[i] * delta;
+            world.positions[i] += world.velocities[i] * delta;
+        }
+    }
+}
+
+// kick, drift, kick: half the velocity change with the forces at the end of the last step,
+// full move, the other half with the forces at the new positions. one force evaluation per
+// step, the first kick misses what viscosity, collisions and bodies did after the last one
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn step(&self, world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+        // first step, the last one ran another scheme or particles were added since
+        if world
+            .end_accelerations_step
+            .is_none_or(|step| step + 1 != world.steps)
+        {
+            world.evaluate_accelerations(world.positions.clone(), config);
+        }
+        for i in 0..world.len() {
+            world.velocities[i] += world.accelerations[i] * delta / 2f32;
+            world.positions[i] += world.velocities[i] * delta;
+        }
+        world.evaluate_accelerations(world.positions.clone(), config);
+        for i in 0..world.len() {
+            world.velocities[i] += world.accelerations[i] * delta / 2f32;
+        }
+        world.end_accelerations_step = Some(world.steps);
+    }
+}
+
+// second order position update with the forces at the start, velocity from the average
+// of those and the new forces. drag sees the velocity predicted with the start forces.
+// unlike leapfrog the start is evaluated again every step, so it sees everything that
+// happened since the end of the last one
+pub struct VelocityVerlet;
+
+impl Integrator for VelocityVerlet {
+    fn step(&self, world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+        world.evaluate_accelerations(world.positions.clone(), config);
+        let last_accelerations = world.accelerations.clone();
+        let last_velocities = world.velocities.clone();
+        let moving = world.positions.iter_mut().zip(&mut world.velocities);
+        for ((position, velocity), acceleration) in moving.zip(&last_accelerations) {
+            *position += *velocity * delta + acceleration * delta * delta / 2f32;
+            *velocity += acceleration * delta;
+        }
+        world.evaluate_accelerations(world.positions.clone(), config);
+        for i in 0..world.len() {
+            world.velocities[i] = last_velocities[i]
+                + (last_accelerations[i] + world.accelerations[i]) * delta / 2f32;
+        }
+    }
+}
+
+// midpoint method, two force evaluations per step
+pub struct Rk2;
+
+impl Integrator for Rk2 {
+    fn step(&self, world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+        let start_positions = world.positions.clone();
+        let start_velocities = world.velocities.clone();
+        world.evaluate_accelerations(start_positions.clone(), config);
+
+        let midpoint_positions: Vec<Vec2> = (0..world.len())
+            .map(|i| start_positions[i] + start_velocities[i] * delta / 2f32)
+            .collect();
+        let midpoint_velocities: Vec<Vec2> = (0..world.len())
+            .map(|i| start_velocities[i] + world.accelerations[i] * delta / 2f32)
+            .collect();
+        world.velocities.clone_from(&midpoint_velocities);
+        world.evaluate_accelerations(midpoint_positions, config);
+
+        for i in 0..world.len() {
+            world.positions[i] = start_positions[i] + midpoint_velocities[i] * delta;
+            world.velocities[i] = start_velocities[i] + world.accelerations[i] * delta;
+        }
+    }
+}
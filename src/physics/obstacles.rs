@@ -0,0 +1,285 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collisions::{closest_point_on_segment, particle_radius, points_bounds, polygon_contains},
+    scenario::encloses_area,
+};
+
+// step of the central differences the surface normal is taken with
+const NORMAL_EPSILON: f32 = 0.05f32;
+// size of the cells the outline is traced in, in pixels
+const OUTLINE_RESOLUTION: f32 = 2f32;
+
+// static solid of any shape described by a signed distance field, negative inside
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Obstacle {
+    pub shape: SdfShape,
+    // share of the speed into the obstacle particles bounce back with
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    // share of the speed along the surface particles lose on every hit
+    #[serde(default)]
+    pub friction: f32,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SdfShape {
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+    // every point closer than `radius` to the segment
+    Capsule {
+        start: Vec2,
+        end: Vec2,
+        radius: f32,
+    },
+    Box {
+        center: Vec2,
+        size: Vec2,
+        // degrees, counter clockwise
+        #[serde(default)]
+        angle: f32,
+    },
+    // any simple polygon, convex or not
+    Polygon {
+        points: Vec<Vec2>,
+    },
+    Union {
+        shapes: Vec<SdfShape>,
+    },
+    // `base` with `cut` carved out of it
+    Subtraction {
+        base: Box<SdfShape>,
+        cut: Box<SdfShape>,
+    },
+}
+
+fn default_restitution() -> f32 {
+    0.5f32
+}
+
+impl SdfShape {
+    pub fn distance(&self, point: Vec2) -> f32 {
+        match self {
+            SdfShape::Circle { center, radius } => point.distance(*center) - radius,
+            SdfShape::Capsule { start, end, radius } => {
+                point.distance(closest_point_on_segment(point, *start, *end)) - radius
+            }
+            SdfShape::Box {
+                center,
+                size,
+                angle,
+            } => {
+                let local = Mat2::from_angle(-angle.to_radians()) * (point - *center);
+                let outside = local.abs() - *size / 2f32;
+                outside.max(Vec2::ZERO).length() + outside.max_element().min(0f32)
+            }
+            SdfShape::Polygon { points } => polygon_distance(points, point),
+            SdfShape::Union { shapes } => shapes
+                .iter()
+                .map(|shape| shape.distance(point))
+                .fold(f32::MAX, f32::min),
+            SdfShape::Subtraction { base, cut } => base.distance(point).max(-cut.distance(point)),
+        }
+    }
+
+    // direction the distance grows fastest in, out of the obstacle
+    pub fn normal(&self, point: Vec2) -> Vec2 {
+        let dx = vec2(NORMAL_EPSILON, 0f32);
+        let dy = vec2(0f32, NORMAL_EPSILON);
+        vec2(
+            self.distance(point + dx) - self.distance(point - dx),
+            self.distance(point + dy) - self.distance(point - dy),
+        )
+        .normalize_or(Vec2::Y)
+    }
+
+    // box around every point with a negative distance
+    fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            SdfShape::Circle { center, radius } => (*center - *radius, *center + *radius),
+            SdfShape::Capsule { start, end, radius } => {
+                (start.min(*end) - *radius, start.max(*end) + *radius)
+            }
+            // the corners can't get further from the center than half the diagonal
+            SdfShape::Box { center, size, .. } => {
+                let reach = size.length() / 2f32;
+                (*center - reach, *center + reach)
+            }
+            SdfShape::Polygon { points } => points_bounds(points),
+            SdfShape::Union { shapes } => shapes.iter().map(|shape| shape.bounds()).fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), (shape_min, shape_max)| (min.min(shape_min), max.max(shape_max)),
+            ),
+            SdfShape::Subtraction { base, .. } => base.bounds(),
+        }
+    }
+
+    // `name` is where the shape is in the file, like `obstacle[0]`
+    pub fn find_invalid_values(&self, name: &str, invalid: &mut Vec<String>) {
+        let finite = match self {
+            SdfShape::Circle { center, radius } => center.is_finite() && radius.is_finite(),
+            SdfShape::Capsule { start, end, radius } => {
+                start.is_finite() && end.is_finite() && radius.is_finite()
+            }
+            SdfShape::Box {
+                center,
+                size,
+                angle,
+            } => center.is_finite() && size.is_finite() && angle.is_finite(),
+            SdfShape::Polygon { points } => points.iter().all(|point| point.is_finite()),
+            // the inner shapes are checked on their own
+            SdfShape::Union { .. } | SdfShape::Subtraction { .. } => true,
+        };
+        if !finite {
+            invalid.push(format!("{}: every value must be finite", name));
+            return;
+        }
+        match self {
+            SdfShape::Circle { radius, .. } | SdfShape::Capsule { radius, .. }
+                if *radius <= 0f32 =>
+            {
+                invalid.push(format!("{}.radius: must be greater than 0", name))
+            }
+            SdfShape::Box { size, .. } if size.cmple(Vec2::ZERO).any() => {
+                invalid.push(format!("{}.size: both sides must be greater than 0", name))
+            }
+            SdfShape::Polygon { points } if points.len() < 3 => {
+                invalid.push(format!("{}.points: needs at least 3 points", name))
+            }
+            SdfShape::Polygon { points } if !encloses_area(points) => {
+                invalid.push(format!("{}.points: must enclose an area", name))
+            }
+            SdfShape::Union { shapes } => {
+                if shapes.is_empty() {
+                    invalid.push(format!("{}.shapes: needs at least 1 shape", name));
+                }
+                for (i, shape) in shapes.iter().enumerate() {
+                    shape.find_invalid_values(&format!("{}.shapes[{}]", name, i), invalid);
+                }
+            }
+            SdfShape::Subtraction { base, cut } => {
+                base.find_invalid_values(&format!("{}.base", name), invalid);
+                cut.find_invalid_values(&format!("{}.cut", name), invalid);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Obstacle {
+    // pushes a particle that got closer than its radius back out along the normal,
+    // bouncing with the restitution and slowing down along the surface with the friction
+    pub fn resolve_collision(&self, position: &mut Vec2, velocity: &mut Vec2) {
+        let radius = particle_radius();
+        let distance = self.shape.distance(*position);
+        if distance >= radius {
+            return;
+        }
+        let normal = self.shape.normal(*position);
+        *position += normal * (radius - distance);
+        let normal_speed = velocity.dot(normal);
+        if normal_speed < 0f32 {
+            let tangent_velocity = *velocity - normal * normal_speed;
+            *velocity = tangent_velocity * (1f32 - self.friction)
+                - normal * normal_speed * self.restitution;
+        }
+    }
+
+    // line segments along the zero distance, traced with marching squares so unions and
+    // subtractions come out right. only the part inside the container of `box_size` is traced
+    pub fn outline(&self, box_size: Vec2) -> Vec<(Vec2, Vec2)> {
+        let (min, max) = self.shape.bounds();
+        let (min, max) = (min.max(-box_size / 2f32), max.min(box_size / 2f32));
+        if min.cmpgt(max).any() {
+            return Vec::new();
+        }
+        let start = min - OUTLINE_RESOLUTION;
+        let cells = ((max - min) / OUTLINE_RESOLUTION).ceil().as_uvec2() + 2;
+        let corner = |x: u32, y: u32| start + vec2(x as f32, y as f32) * OUTLINE_RESOLUTION;
+        let distances: Vec<f32> = (0..=cells.y)
+            .flat_map(|y| (0..=cells.x).map(move |x| (x, y)))
+            .map(|(x, y)| self.shape.distance(corner(x, y)))
+            .collect();
+        let distance_at = |x: u32, y: u32| distances[(y * (cells.x + 1) + x) as usize];
+
+        let mut segments = Vec::new();
+        for y in 0..cells.y {
+            for x in 0..cells.x {
+                // corners counter clockwise from the bottom left, crossings on the edges after them
+                let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+                let values = corners.map(|(x, y)| distance_at(x, y));
+                let mut crossings = Vec::with_capacity(4);
+                for i in 0..4 {
+                    let (a, b) = (values[i], values[(i + 1) % 4]);
+                    if (a < 0f32) != (b < 0f32) {
+                        let (corner_a, corner_b) = (corners[i], corners[(i + 1) % 4]);
+                        let t = a / (a - b);
+                        crossings.push(
+                            corner(corner_a.0, corner_a.1).lerp(corner(corner_b.0, corner_b.1), t),
+                        );
+                    }
+                }
+                match crossings[..] {
+                    [a, b] => segments.push((a, b)),
+                    // opposite corners inside, the center decides whether they're connected
+                    [a, b, c, d] => {
+                        let center = self
+                            .shape
+                            .distance(corner(x, y) + Vec2::splat(OUTLINE_RESOLUTION / 2f32));
+                        if (center < 0f32) == (values[0] < 0f32) {
+                            segments.push((a, b));
+                            segments.push((c, d));
+                        } else {
+                            segments.push((d, a));
+                            segments.push((b, c));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        segments
+    }
+}
+
+// obstacles don't move, their outlines are only traced again when they change
+#[derive(Default)]
+pub struct ObstacleOutlines {
+    obstacles: Vec<Obstacle>,
+    box_size: Vec2,
+    segments: Vec<(Vec2, Vec2)>,
+}
+
+impl ObstacleOutlines {
+    pub fn segments(&mut self, obstacles: &[Obstacle], box_size: Vec2) -> &[(Vec2, Vec2)] {
+        if self.obstacles != obstacles || self.box_size != box_size {
+            self.segments = obstacles
+                .iter()
+                .flat_map(|obstacle| obstacle.outline(box_size))
+                .collect();
+            self.obstacles = obstacles.to_vec();
+            self.box_size = box_size;
+        }
+        &self.segments
+    }
+}
+
+// distance to the closest edge, negative inside by the even-odd rule
+fn polygon_distance(points: &[Vec2], point: Vec2) -> f32 {
+    let distance = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            point.distance(closest_point_on_segment(point, a, b))
+        })
+        .fold(f32::MAX, f32::min);
+    if polygon_contains(points, point) {
+        -distance
+    } else {
+        distance
+    }
+}
@@ -1,201 +1,179 @@
 use crate::{
-    collisions::resolve_collisions,
-    particle_grid,
-    particles_spawning::{self, PARTICLES_COUNT},
-    player_interaction_physics,
-    pressure_handler::{self, calculate_pressure_force},
-    viscosity_force::calculate_viscosity_force,
+    fluid_kind::FluidKind,
+    fluid_world::{FluidWorld, InteractionInput},
+    sim_config::SimConfig,
+    time_step,
 };
-use bevy::{math::*, prelude::*, window::PrimaryWindow};
+use bevy::{ecs::system::SystemParam, math::*, prelude::*, window::PrimaryWindow};
 
-// physics settings
-const GRAVITY: Vec2 = Vec2::new(0f32, -15f32);
-const TIME_SCALE: f32 = 2f32;
-const AIR_DENSITY: f32 = 1f32;
-const PARTICLE_DRAG_COEFFICIENT: f32 = 0.01f32;
-const PRESSURE_FORCE_MODIFIER: f32 = 0.25f32;
-
-const DEBUG_USE_PRESSURE: bool = true;
 const RUN_PHYSICS: bool = true;
-const UPDATES_PER_FRAME: u32 = 3;
+// frames that are an exact multiple of the step shouldn't lose one to rounding
+const STEP_TOLERANCE: f32 = 1e-4f32;
+
+// simulated time the frames produced but no physics step consumed yet
+#[derive(Resource, Default)]
+pub struct PhysicsClock {
+    accumulator: f32,
+    // shown in the ui
+    pub last_step: f32,
+    pub last_substeps: u32,
+}
+
+impl PhysicsClock {
+    // runs the physics steps for the accumulated time and returns how far the
+    // leftover time is into the next step, from 0 to 1
+    pub fn advance(&mut self, world: &mut FluidWorld, config: &SimConfig, frame_delta: f32) -> f32 {
+        self.accumulator += frame_delta;
+        self.last_substeps = 0;
+        if config.adaptive_time_step {
+            self.advance_adaptive(world, config);
+            return 1f32;
+        }
+
+        let step = config.physics_step;
+        while self.accumulator >= step * (1f32 - STEP_TOLERANCE) {
+            if self.last_substeps == config.max_steps_per_frame {
+                // a hitch slows the simulation down instead of feeding it a huge time step
+                self.accumulator = 0f32;
+                break;
+            }
+            world.step(config, step);
+            self.accumulator -= step;
+            self.last_substeps += 1;
+            self.last_step = step;
+        }
+        (self.accumulator / step).clamp(0f32, 1f32)
+    }
+
+    // subdivides the whole frame into steps no larger than the stable one, nothing is left over
+    fn advance_adaptive(&mut self, world: &mut FluidWorld, config: &SimConfig) {
+        while self.accumulator > 0f32 {
+            if self.last_substeps == config.max_steps_per_frame {
+                self.accumulator = 0f32;
+                break;
+            }
+            let stable_step = time_step::adaptive_time_step(world, config);
+            // equal substeps for the rest of the frame, so the last one isn't a sliver
+            let remaining_steps = (self.accumulator / stable_step).ceil();
+            let step = self.accumulator / remaining_steps;
+            world.step(config, step);
+            self.accumulator = if remaining_steps <= 1f32 {
+                0f32
+            } else {
+                self.accumulator - step
+            };
+            self.last_substeps += 1;
+            self.last_step = step;
+        }
+    }
+}
+
 pub fn handle_particles_physics(
+    mut world: ResMut<FluidWorld>,
+    mut clock: ResMut<PhysicsClock>,
+    config: Res<SimConfig>,
     mut particles: Query<(&mut Transform, &mut Particle)>,
     time: Res<Time>,
-    q_window: Query<&Window, With<PrimaryWindow>>,
-    mouse_buttons: Res<ButtonInput<MouseButton>>,
-    q_camera: Query<(&Camera, &GlobalTransform)>,
+    inputs: InteractionInputs,
 ) {
     if !RUN_PHYSICS {
         return;
     }
 
-    let delta = time.delta().as_secs_f32() * TIME_SCALE / UPDATES_PER_FRAME as f32;
-    for _ in 0..UPDATES_PER_FRAME {
-        particles
-            .par_iter_mut()
-            .for_each(|(transform, mut particle)| {
-                let a = GRAVITY;
-                particle.velocity += a * delta;
-                particle.predicted_position =
-                    transform.translation.xy() + particle.velocity / 120f32;
-            });
-
-        let mut particle_predicted_positions =
-            Vec::with_capacity(particles_spawning::PARTICLES_COUNT as usize);
-        for (_, particle) in &particles {
-            particle_predicted_positions.push(particle.predicted_position);
-        }
-
-        let connected_cells = particle_grid::calculate_connected_cells_for_every_particle(
-            &particle_predicted_positions,
-        );
-
-        let grid = particle_grid::split_particles_into_grid(&particle_predicted_positions);
-
-        let densities = &pressure_handler::calculate_density_for_every_particle(
-            &grid,
-            &particle_predicted_positions,
-            &connected_cells,
-        );
-
-        // interactions
-        let mut use_interaction: bool = true;
-        // get the camera info and transform
-        // assuming there is exactly one main camera entity, so Query::single() is OK
-        let (camera, camera_transform) = q_camera.single();
-
-        // There is only one primary window, so we can similarly get it from the query:
-        let window = q_window.single();
-
-        // check if the cursor is inside the window and get its position
-        // then, ask bevy to convert into world coordinates, and truncate to discard Z
-        let cursor_option = window.cursor_position();
-        let mouse_position = match cursor_option {
-            Some(pos) => camera
-                .viewport_to_world(camera_transform, pos)
-                .map(|ray| ray.origin.truncate())
-                .unwrap(),
-            None => {
-                use_interaction = false;
-                Vec2::ZERO
-            }
-        };
-        let force_sign;
-        if mouse_buttons.pressed(MouseButton::Right) {
-            force_sign = -1f32;
-        } else if mouse_buttons.pressed(MouseButton::Left) {
-            // disabled because not working good enough
-            use_interaction = false;
-            force_sign = 0.5f32;
-        } else {
-            use_interaction = false;
-            force_sign = 0f32;
-        };
-
-        particles.par_iter_mut().for_each(|(_, mut particle)| {
-            let pressure_force: Vec2 = if DEBUG_USE_PRESSURE {
-                -calculate_pressure_force(
-                    particle.index,
-                    connected_cells
-                        .get(particle.index * 9..(particle.index + 1) * 9)
-                        .unwrap(),
-                    &particle_predicted_positions,
-                    &grid,
-                    densities,
-                )
-            } else {
-                Vec2::ZERO
-            };
+    world.interaction = read_interaction_input(&inputs);
 
-            let interaction_force = match use_interaction {
-                true => player_interaction_physics::calculate_interaction_force(
-                    particle.predicted_position,
-                    mouse_position,
-                    force_sign,
-                    particle.velocity,
-                ),
-                false => Vec2::ZERO,
-            };
+    let frame_delta = config.frame_delta(time.delta_secs());
+    let blend = clock.advance(&mut world, &config, frame_delta);
 
-            let force = pressure_force * PRESSURE_FORCE_MODIFIER
-                - calc_drag_force(particle.velocity, particle.area)
-                + interaction_force;
-            let acceleration = force / particle.mass;
-            particle.velocity += acceleration * delta;
-        });
-        let mut velocities: Vec<Vec2> = Vec::with_capacity(PARTICLES_COUNT as usize);
-        // get velocities for viscosity
-        particles.iter().for_each(|(_, particle)| {
-            velocities.push(particle.velocity);
-        });
+    sync_particles_with_world(&world, blend, &mut particles);
+}
 
-        // apply viscosity
-        particles.par_iter_mut().for_each(|(_, mut particle)| {
-            let viscosity = calculate_viscosity_force(
-                particle.predicted_position,
-                particle.velocity,
-                &particle_predicted_positions,
-                // could add that as a separate property to particle so i don't have to get it twice
-                // but that's a pretty cheap operation
-                connected_cells
-                    .get(&particle.index * 9..(&particle.index + 1) * 9)
-                    .unwrap(),
-                &grid,
-                &velocities,
-            );
-            particle.velocity += viscosity;
+// copies the solver state back onto the entities used for rendering,
+// `blend` interpolates positions between the last two steps so motion stays smooth
+pub fn sync_particles_with_world(
+    world: &FluidWorld,
+    blend: f32,
+    particles: &mut Query<(&mut Transform, &mut Particle)>,
+) {
+    particles
+        .par_iter_mut()
+        .for_each(|(mut transform, mut particle)| {
+            let index = particle.index;
+            if index >= world.len() {
+                return;
+            }
+            let pos = world.previous_positions[index].lerp(world.positions[index], blend);
+            transform.translation = vec3(pos.x, pos.y, transform.translation.z);
+            particle.velocity = world.velocities[index];
+            particle.last_velocity = world.last_velocities[index];
+            particle.predicted_position = world.predicted_positions[index];
+            // just for visualization purposes
+            particle.density = world.densities[index];
+            particle.temperature = world.temperatures[index];
+            particle.frozen = world.is_frozen(index);
         });
+}
 
-        particles
-            .par_iter_mut()
-            .for_each(|(mut transform, mut particle)| {
-                if particle.velocity.is_nan() {
-                    particle.velocity = particle.last_velocity;
-                }
-                particle.last_velocity = particle.velocity;
-
-                let s = particle.velocity * delta;
-
-                transform.translation += vec3(s.x, s.y, 0f32);
-                resolve_collisions(&mut particle, &mut transform);
-                // just for visualization purposes
-                particle.density = densities[particle.index];
-            });
-    }
+// everything needed to turn the mouse into a force on the fluid
+#[derive(SystemParam)]
+pub struct InteractionInputs<'w, 's> {
+    q_window: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    q_camera: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
 }
 
-fn calc_drag_force(velocity: Vec2, area: f32) -> Vec2 {
-    // F = .5*d*v^2*C*A https://en.wikipedia.org/wiki/Drag_(physics)
-    let speed_squared = velocity.length_squared();
-    AIR_DENSITY * speed_squared * PARTICLE_DRAG_COEFFICIENT * area / 2f32 * velocity.normalize()
+fn read_interaction_input(inputs: &InteractionInputs) -> Option<InteractionInput> {
+    let force_sign = if inputs.mouse_buttons.pressed(MouseButton::Right) {
+        -1f32
+    } else {
+        // left button (0.5f32) disabled because not working good enough
+        return None;
+    };
+
+    // get the camera info and transform
+    // assuming there is exactly one main camera entity, so Query::single() is OK
+    let (camera, camera_transform) = inputs.q_camera.single();
+
+    // There is only one primary window, so we can similarly get it from the query:
+    let window = inputs.q_window.single();
+
+    // check if the cursor is inside the window and get its position
+    // then, ask bevy to convert into world coordinates, and truncate to discard Z
+    let cursor_position = window.cursor_position()?;
+    let mouse_position = camera
+        .viewport_to_world(camera_transform, cursor_position)
+        .map(|ray| ray.origin.truncate())
+        .ok()?;
+
+    Some(InteractionInput {
+        position: mouse_position,
+        force_sign,
+    })
 }
 
 #[derive(Component)]
-pub(crate) struct Particle {
-    pub ray: f32,
-    pub mass: f32,
+pub struct Particle {
     pub velocity: Vec2,
     pub last_velocity: Vec2,
-    pub area: f32,
     pub index: usize,
     pub predicted_position: Vec2,
     // used for visuals
     pub density: f32,
+    pub fluid: FluidKind,
+    pub temperature: f32,
+    pub frozen: bool,
 }
 impl Particle {
-    pub fn new(mass: f32, velocity: Vec2, ray: f32, index: usize) -> Particle {
+    pub fn new(velocity: Vec2, index: usize, fluid: FluidKind) -> Particle {
         Particle {
-            ray,
-            mass,
             velocity,
             last_velocity: Vec2::ZERO,
-            area: Particle::calc_area(ray),
             index,
             predicted_position: Vec2::ZERO,
             density: 0f32,
+            fluid,
+            temperature: 0f32,
+            frozen: false,
         }
     }
-    fn calc_area(ray: f32) -> f32 {
-        core::f32::consts::PI * ray.squared() * particles_spawning::PARTICLE_RESOLUTION
-    }
 }
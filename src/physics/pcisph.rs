@@ -0,0 +1,280 @@
+use bevy::math::*;
+
+use crate::{
+    collisions::{container_half_size, resolve_collisions},
+    fluid_world::{FluidWorld, SolverStats, par_map_particles},
+    pressure_handler::INFLUENCE_MODIFIER,
+    rigid_body,
+    sim_config::SimConfig,
+};
+
+// the one step pressure estimate ignores that neighbours get corrected at the same time,
+// taking it whole makes the iterations oscillate and blow up where the fluid is stacked
+const RELAXATION: f32 = 0.3;
+
+// predictive-corrective incompressible SPH (Solenthaler and Pajarola 2009):
+// predicts where pressure would move the particles, corrects pressure from the density
+// error there and repeats until the predicted fluid is barely compressed.
+// neighbours come from the grid built at the start of the step
+pub fn step(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    world.update_neighbourhoods(world.positions.clone(), config);
+    let particles_count = world.len();
+    let external = par_map_particles(particles_count, |i| world.external_acceleration(i, config));
+    let pressure_scales = par_map_particles(particles_count, |i| {
+        pressure_scaling_factor(world, i, config, delta)
+    });
+
+    let mut pressures = vec![0f32; particles_count];
+    let mut body_pressure_terms = vec![0f32; particles_count];
+    let mut pressure_accelerations = vec![Vec2::ZERO; particles_count];
+    let mut stats = SolverStats::default();
+    while stats.iterations < config.pressure_solve.max_iterations {
+        let predicted_positions = par_map_particles(particles_count, |i| {
+            let mut velocity =
+                world.velocities[i] + (external[i] + pressure_accelerations[i]) * delta;
+            let mut position = world.positions[i] + velocity * delta;
+            // particles pushed into the container don't leave room for their neighbours
+            resolve_collisions(
+                &mut position,
+                &mut velocity,
+                &world.walls,
+                &world.obstacles,
+                config,
+            );
+            position
+        });
+        let predicted_densities = par_map_particles(particles_count, |i| {
+            sample_density(world, &predicted_positions, i, config)
+        });
+
+        // only compression is corrected, stretched particles at the surface keep 0 pressure.
+        // the predicted densities are neighbour counts, every fluid is as much denser than
+        // that as it's heavier
+        let mut max_compression = 0f32;
+        for i in 0..particles_count {
+            let rest_density = config.rest_density(world.fluid_kinds[i]);
+            let density_error =
+                predicted_densities[i] * world.relative_density(i, config) - rest_density;
+            pressures[i] = (pressures[i] + pressure_scales[i] * density_error).max(0f32);
+            max_compression = max_compression.max(density_error / rest_density);
+        }
+        stats.density_error = max_compression;
+
+        // the samples only push with the particle's own pressure term
+        body_pressure_terms = par_map_particles(particles_count, |i| {
+            pressures[i] / predicted_densities[i].powi(2) * INFLUENCE_MODIFIER
+                / world.relative_density(i, config)
+        });
+        pressure_accelerations = par_map_particles(particles_count, |i| {
+            pressure_acceleration(
+                world,
+                &predicted_positions,
+                &predicted_densities,
+                &pressures,
+                i,
+                config,
+            ) + rigid_body::boundary_acceleration(
+                world,
+                predicted_positions[i],
+                body_pressure_terms[i],
+                config,
+            )
+        });
+        stats.iterations += 1;
+        if stats.iterations >= config.pressure_solve.min_iterations
+            && stats.density_error <= config.pressure_solve.max_density_error
+        {
+            break;
+        }
+    }
+
+    world.accelerations = (0..particles_count)
+        .map(|i| external[i] + pressure_accelerations[i])
+        .collect();
+    world.update_max_acceleration();
+    for i in 0..particles_count {
+        world.velocities[i] += world.accelerations[i] * delta;
+        world.positions[i] += world.velocities[i] * delta;
+    }
+    world.pressures = pressures;
+    world.body_pressure_terms = body_pressure_terms;
+    world.solver_stats = stats;
+}
+
+// neighbour position plus its mirror images across the container sides close to the
+// sample point, with the axes each image is flipped on (-1) to mirror velocities too.
+// without them particles along the container miss half their neighbours,
+// never reach the target density and the fluid above crushes them.
+// sides are mirrored half a rest spacing out, so a particle lying on the container
+// sees its own image one spacing away like in the rest of the fluid
+pub fn mirrored_positions(
+    position: Vec2,
+    sample_point: Vec2,
+    config: &SimConfig,
+) -> [Option<(Vec2, Vec2)>; 5] {
+    let half_size = container_half_size(config) + Vec2::splat(config.particle_spacing / 2f32);
+    let h = config.smoothing_distance;
+    [
+        Some((position, Vec2::ONE)),
+        (sample_point.x > half_size.x - h).then(|| {
+            (
+                vec2(2f32 * half_size.x - position.x, position.y),
+                vec2(-1f32, 1f32),
+            )
+        }),
+        (sample_point.x < h - half_size.x).then(|| {
+            (
+                vec2(-2f32 * half_size.x - position.x, position.y),
+                vec2(-1f32, 1f32),
+            )
+        }),
+        (sample_point.y > half_size.y - h).then(|| {
+            (
+                vec2(position.x, 2f32 * half_size.y - position.y),
+                vec2(1f32, -1f32),
+            )
+        }),
+        (sample_point.y < h - half_size.y).then(|| {
+            (
+                vec2(position.x, -2f32 * half_size.y - position.y),
+                vec2(1f32, -1f32),
+            )
+        }),
+    ]
+}
+
+// every neighbour of `index` with its position and mirror flip, mirrored ones included
+fn neighbour_positions<'a>(
+    world: &'a FluidWorld,
+    positions: &'a [Vec2],
+    index: usize,
+    config: &'a SimConfig,
+) -> impl Iterator<Item = (usize, Vec2, Vec2)> + 'a {
+    let sample_point = positions[index];
+    world.neighbours(index).flat_map(move |j| {
+        mirrored_positions(positions[j], sample_point, config)
+            .into_iter()
+            .flatten()
+            .map(move |(position, flip)| (j, position, flip))
+    })
+}
+
+// neighbours pressure acts between, the density the solvers correct only counts these
+pub fn pushing_neighbour_positions<'a>(
+    world: &'a FluidWorld,
+    positions: &'a [Vec2],
+    index: usize,
+    config: &'a SimConfig,
+) -> impl Iterator<Item = (usize, Vec2, Vec2)> + 'a {
+    neighbour_positions(world, positions, index, config)
+        .filter(move |(j, _, _)| world.pushes_apart(index, *j))
+}
+
+// sum of the density kernel gradients around `index` and sum of their squares,
+// how strongly the density of a particle reacts to it and its neighbours moving.
+// the squares are weighted by how much lighter the neighbour is, it moves that much
+// further under the same pressure. the bodies don't move with the fluid, their samples
+// only add to the sum
+pub fn gradient_sums(
+    world: &FluidWorld,
+    positions: &[Vec2],
+    index: usize,
+    config: &SimConfig,
+) -> (Vec2, f32) {
+    let kernel = config.kernels.density.kernel();
+    let relative_density = world.relative_density(index, config);
+    let mut gradient_sum = rigid_body::boundary_gradient(world, positions[index], config);
+    let mut gradient_dot_sum = 0f32;
+    for (j, position, _) in pushing_neighbour_positions(world, positions, index, config) {
+        let gradient = kernel.gradient(positions[index] - position, config.smoothing_distance);
+        if j == index {
+            // the own mirror image moves the opposite way, closing in twice as fast
+            gradient_sum += 2f32 * gradient;
+        } else {
+            gradient_sum += gradient;
+            gradient_dot_sum +=
+                gradient.dot(gradient) * relative_density / world.relative_density(j, config);
+        }
+    }
+    (gradient_sum, gradient_dot_sum)
+}
+
+// neighbour count of `index` in density units, the same for every fluid at rest.
+// ice next to ice doesn't count, it can't be pushed away to fix the density.
+// the bodies count like the fluid they keep out
+pub fn sample_density(
+    world: &FluidWorld,
+    positions: &[Vec2],
+    index: usize,
+    config: &SimConfig,
+) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    pushing_neighbour_positions(world, positions, index, config)
+        .map(|(_, position, _)| {
+            kernel.value(
+                positions[index].distance(position),
+                config.smoothing_distance,
+            ) * INFLUENCE_MODIFIER
+        })
+        .sum::<f32>()
+        + rigid_body::boundary_density(world, positions[index], config)
+}
+
+// symmetric SPH pressure gradient on neighbour counts, every particle weights
+// INFLUENCE_MODIFIER like in the density. the pairs push equally, so a heavier particle
+// is accelerated less
+fn pressure_acceleration(
+    world: &FluidWorld,
+    positions: &[Vec2],
+    densities: &[f32],
+    pressures: &[f32],
+    index: usize,
+    config: &SimConfig,
+) -> Vec2 {
+    let kernel = config.kernels.pressure.kernel();
+    let own_term = pressures[index] / densities[index].powi(2);
+    let mut acceleration = Vec2::ZERO;
+    for (j, position, _) in pushing_neighbour_positions(world, positions, index, config) {
+        let gradient = kernel.gradient(positions[index] - position, config.smoothing_distance);
+        acceleration -= (own_term + pressures[j] / densities[j].powi(2)) * gradient;
+    }
+    acceleration * INFLUENCE_MODIFIER / world.relative_density(index, config)
+}
+
+// turns a density error into the pressure that undoes it in one step (the "delta" of
+// the paper), taken from the real neighbourhood since the textbook full-neighbourhood one
+// overshoots several times at the free surface
+fn pressure_scaling_factor(
+    world: &FluidWorld,
+    index: usize,
+    config: &SimConfig,
+    delta: f32,
+) -> f32 {
+    let (gradient_sum, gradient_dot_sum) = gradient_sums(world, &world.positions, index, config);
+    let beta = 2f32 * (delta * INFLUENCE_MODIFIER / config.water_rest_density).powi(2);
+    let denominator = beta * (gradient_sum.dot(gradient_sum) + gradient_dot_sum);
+    if denominator > 0f32 {
+        RELAXATION / denominator
+    } else {
+        0f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fluid_world::tests::settle_block, sim_config::SolverKind};
+
+    // a block dropped into a small container spreads out and comes to rest, the pressure
+    // keeps it from getting squeezed and never adds energy
+    #[test]
+    fn block_settles() {
+        let block = settle_block(SolverKind::Pcisph);
+        assert!(block.peak_energy <= block.start_energy);
+        let density_error = block.world.solver_stats.density_error;
+        assert!(
+            density_error <= block.config.pressure_solve.max_density_error,
+            "{}",
+            density_error
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use bevy::math::*;
+
+use crate::{fluid_world::FluidWorld, sim_config::SimConfig};
+
+// particles count as ice below half liquid, so freezing and melting switch at the same point
+const FROZEN_BELOW: f32 = 0.5f32;
+
+// frozen particles that touch, held in the shape they had when the cluster formed
+#[derive(Clone, Default)]
+pub struct IceCluster {
+    pub particles: Vec<usize>,
+    pub rest_offsets: Vec<Vec2>,
+}
+
+pub fn is_frozen(liquid_fraction: f32) -> bool {
+    liquid_fraction < FROZEN_BELOW
+}
+
+// regions and emitters colder than the freezing temperature start out as ice
+pub fn initial_liquid_fraction(temperature: f32, config: &SimConfig) -> f32 {
+    match config.heat.freezing_temperature {
+        Some(freezing_temperature) if temperature < freezing_temperature => 0f32,
+        _ => 1f32,
+    }
+}
+
+// trades temperature for latent heat: a particle colder than the freezing temperature
+// freezes a bit and warms back up towards it, a warmer one melts and cools down.
+// ice clusters are rebuilt whenever a particle froze or melted
+pub fn change_phase(world: &mut FluidWorld, config: &SimConfig) {
+    let Some(freezing_temperature) = config.heat.freezing_temperature else {
+        // freezing got turned off, everything melts so no cluster holds on to stale ice
+        world.liquid_fractions.fill(1f32);
+        world.ice_clusters.clear();
+        return;
+    };
+    let latent_heat = config.heat.latent_heat;
+    let mut changed = false;
+    let mut frozen_count = 0;
+    for (temperature, liquid_fraction) in world
+        .temperatures
+        .iter_mut()
+        .zip(&mut world.liquid_fractions)
+    {
+        let was_frozen = is_frozen(*liquid_fraction);
+        let excess = *temperature - freezing_temperature;
+        let new_fraction = if latent_heat > 0f32 {
+            (*liquid_fraction + excess / latent_heat).clamp(0f32, 1f32)
+        } else if excess > 0f32 {
+            1f32
+        } else if excess < 0f32 {
+            0f32
+        } else {
+            *liquid_fraction
+        };
+        *temperature -= (new_fraction - *liquid_fraction) * latent_heat;
+        *liquid_fraction = new_fraction;
+        changed |= was_frozen != is_frozen(new_fraction);
+        frozen_count += is_frozen(new_fraction) as usize;
+    }
+    // also catches frozen particles that came from spawning or an emitter
+    let clustered_count: usize = world.ice_clusters.iter().map(|c| c.particles.len()).sum();
+    if changed || clustered_count != frozen_count {
+        world.ice_clusters = build_ice_clusters(world, config);
+    }
+}
+
+// frozen particles closer than the smoothing distance end up in the same cluster
+fn build_ice_clusters(world: &FluidWorld, config: &SimConfig) -> Vec<IceCluster> {
+    let mut visited = vec![false; world.len()];
+    let mut clusters = Vec::new();
+    for start in 0..world.len() {
+        if visited[start] || !world.is_frozen(start) {
+            continue;
+        }
+        visited[start] = true;
+        let mut particles = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(i) = queue.pop_front() {
+            particles.push(i);
+            for j in world.neighbours(i) {
+                if visited[j]
+                    || !world.is_frozen(j)
+                    || world.predicted_positions[i].distance(world.predicted_positions[j])
+                        >= config.smoothing_distance
+                {
+                    continue;
+                }
+                visited[j] = true;
+                queue.push_back(j);
+            }
+        }
+        let center = center_of_mass(world, &particles);
+        let rest_offsets = particles
+            .iter()
+            .map(|i| world.positions[*i] - center)
+            .collect();
+        clusters.push(IceCluster {
+            particles,
+            rest_offsets,
+        });
+    }
+    clusters
+}
+
+// moves every ice cluster back into its rest shape at the best fitting rotation (shape
+// matching, Müller et al. 2005), the forces of the step only move and turn it as a whole
+pub fn hold_ice_rigid(world: &mut FluidWorld, delta: f32) {
+    for cluster in &world.ice_clusters {
+        let center = center_of_mass(world, &cluster.particles);
+        let (mut cos_sum, mut sin_sum) = (0f32, 0f32);
+        for (i, rest_offset) in cluster.particles.iter().zip(&cluster.rest_offsets) {
+            let offset = world.positions[*i] - center;
+            cos_sum += world.masses[*i] * rest_offset.dot(offset);
+            sin_sum += world.masses[*i] * rest_offset.perp_dot(offset);
+        }
+        let rotation = Mat2::from_angle(sin_sum.atan2(cos_sum));
+        for (i, rest_offset) in cluster.particles.iter().zip(&cluster.rest_offsets) {
+            let goal = center + rotation * *rest_offset;
+            world.velocities[*i] = (goal - world.previous_positions[*i]) / delta;
+            world.positions[*i] = goal;
+        }
+    }
+}
+
+fn center_of_mass(world: &FluidWorld, particles: &[usize]) -> Vec2 {
+    let (weighted, mass) = particles
+        .iter()
+        .fold((Vec2::ZERO, 0f32), |(weighted, mass), i| {
+            (
+                weighted + world.positions[*i] * world.masses[*i],
+                mass + world.masses[*i],
+            )
+        });
+    weighted / mass
+}
@@ -1,78 +1,53 @@
 use crate::{
-    particle_grid::TOTAL_GRID_SIZE,
-    particles_spawning::{self, PARTICLES_COUNT},
+    fluid_kind::FluidKind,
+    fluid_world::{FluidWorld, par_map_particles},
+    sim_config::SimConfig,
+    smoothing_kernels::{SmoothingKernel, Spiky},
 };
-use bevy::{math::Vec2, prelude::*, tasks::ParallelSlice};
-use std::f32::consts::PI;
+use bevy::math::Vec2;
 
-// can't use SMOOTHING_DISTANCE.powi(4) so just multiply 4 times
-const SMOOTHING_KERNEL_DERIVATIVE_SCALE: f32 = 12f32
-    / (PI
-        * (SMOOTHING_DISTANCE * SMOOTHING_DISTANCE * SMOOTHING_DISTANCE * SMOOTHING_DISTANCE)
-            as f32);
-
-fn smoothing_kernel_derivative(distance: f32) -> f32 {
-    if distance >= SMOOTHING_DISTANCE as f32 {
-        return 0f32;
-    }
-    (distance - SMOOTHING_DISTANCE as f32) * SMOOTHING_KERNEL_DERIVATIVE_SCALE
-}
-
-// can't use SMOOTHING_DISTANCE.powi(4) so just multiply 4 times
-const SMOOTHING_KERNEL_VOLUME: f32 = (PI
-    * (SMOOTHING_DISTANCE * SMOOTHING_DISTANCE * SMOOTHING_DISTANCE * SMOOTHING_DISTANCE) as f32)
-    / 6f32;
-fn smoothing_kernel(distance: f32) -> f32 {
-    if distance >= SMOOTHING_DISTANCE as f32 {
-        return 0f32;
-    }
-
-    (SMOOTHING_DISTANCE as f32 - distance) * (SMOOTHING_DISTANCE as f32 - distance)
-        / SMOOTHING_KERNEL_VOLUME
-}
+// near density uses a spikier kernel than the density, it barely changes until particles
+// get really close and then its pressure pushes them apart (Clavet double density relaxation)
+const NEAR_DENSITY_KERNEL: Spiky = Spiky;
 
+// density and near density of every particle
 pub fn calculate_density_for_every_particle(
     particles_gird: &[Vec<usize>],
     particles_pos: &[Vec2],
     connected_cells: &[usize],
-) -> Vec<f32> {
-    let input = vec![0f32; particles_spawning::PARTICLES_COUNT as usize];
-    let data_chunks = input.par_splat_map(bevy::tasks::ComputeTaskPool::get(), None, |i, data| {
-        // `i` is the starting index of the current chunk
-        let mut output_chunk = Vec::new();
-
-        for internal_index in 0..data.len() {
-            let real_particle_index = internal_index + i;
-            output_chunk.push(sample_density(
-                &particles_pos[real_particle_index],
-                connected_cells
-                    .get(real_particle_index * 9..(real_particle_index + 1) * 9)
-                    .unwrap(),
-                particles_gird,
-                particles_pos,
-            ));
-        }
-        output_chunk
+    fluid_kinds: &[FluidKind],
+    config: &SimConfig,
+) -> (Vec<f32>, Vec<f32>) {
+    let densities = par_map_particles(particles_pos.len(), |particle_index| {
+        sample_density(
+            &particles_pos[particle_index],
+            fluid_kinds[particle_index],
+            connected_cells
+                .get(particle_index * 9..(particle_index + 1) * 9)
+                .unwrap(),
+            particles_gird,
+            particles_pos,
+            config,
+        )
     });
-    let mut output = Vec::with_capacity(PARTICLES_COUNT as usize);
-    for chunk in data_chunks {
-        for density in chunk {
-            output.push(density);
-        }
-    }
-    output
+    densities.into_iter().unzip()
 }
+// pressure force on `sample_particle_index` from the densities of the last neighbourhood update
 pub fn calculate_pressure_force(
+    world: &FluidWorld,
     sample_particle_index: usize,
-    sample_connected_cells: &[usize],
-    particles_pos: &[Vec2],
-    particle_grid: &[Vec<usize>],
-    densities: &[f32],
+    config: &SimConfig,
 ) -> Vec2 {
+    let sample_connected_cells = world.particle_connected_cells(sample_particle_index);
+    let particles_pos = &world.predicted_positions;
+    let particle_grid = &world.grid;
+    let (densities, near_densities) = (&world.densities, &world.near_densities);
+    let fluid_kinds = &world.fluid_kinds;
     let sample_point = particles_pos[sample_particle_index];
+    let kernel = config.kernels.pressure.kernel();
     let mut pressure: Vec2 = Vec2::ZERO;
     for cell in sample_connected_cells {
-        if cell == &usize::MAX || cell > &TOTAL_GRID_SIZE {
+        if cell == &usize::MAX || cell >= &particle_grid.len() {
             continue;
         }
         for particle_index_ref in &particle_grid[cell.to_owned()] {
@@ -81,55 +56,111 @@ pub fn calculate_pressure_force(
             if particle_index == sample_particle_index || sample_point == pos {
                 continue;
             }
+            if !world.pushes_apart(sample_particle_index, particle_index) {
+                continue;
+            }
 
             let dist = pos.distance(sample_point);
             let dir = (pos - sample_point) / dist;
-            let slope = smoothing_kernel_derivative(dist);
+            let slope = kernel.derivative(dist, config.smoothing_distance);
             let shared_pressure = calculate_shared_pressure(
-                densities[particle_index],
-                densities[sample_particle_index],
+                (densities[particle_index], fluid_kinds[particle_index]),
+                (
+                    densities[sample_particle_index],
+                    fluid_kinds[sample_particle_index],
+                ),
+                config,
             );
-            pressure -=
-                shared_pressure * dir * slope * INFLUENCE_MODIFIER / densities[particle_index];
+            // volume of the neighbour, the same for every fluid at rest
+            let volume = INFLUENCE_MODIFIER
+                * config
+                    .fluids
+                    .get(fluid_kinds[particle_index])
+                    .relative_density
+                / densities[particle_index];
+            pressure -= shared_pressure * dir * slope * volume;
+
+            if config.near_pressure_multiplier > 0f32 {
+                let near_slope = NEAR_DENSITY_KERNEL.derivative(dist, config.smoothing_distance);
+                let shared_near_pressure =
+                    (near_densities[particle_index] + near_densities[sample_particle_index]) / 2f32
+                        * config.near_pressure_multiplier;
+                pressure -= shared_near_pressure * dir * near_slope * INFLUENCE_MODIFIER
+                    / near_densities[particle_index];
+            }
         }
     }
     pressure
 }
-fn calculate_shared_pressure(density_a: f32, density_b: f32) -> f32 {
-    let pressure_a = density_to_pressure(density_a);
-    let pressure_b = density_to_pressure(density_b);
+fn calculate_shared_pressure(
+    (density_a, fluid_a): (f32, FluidKind),
+    (density_b, fluid_b): (f32, FluidKind),
+    config: &SimConfig,
+) -> f32 {
+    let pressure_a = density_to_pressure(density_a, fluid_a, config);
+    let pressure_b = density_to_pressure(density_b, fluid_b, config);
     (pressure_a + pressure_b) / 2f32
 }
-pub const TARGET_DENSITY: f32 = 0.3f32;
-const PRESSURE_MULTIPLIER: f32 = 100000.0f32;
-fn density_to_pressure(density: f32) -> f32 {
-    let density_error = density - TARGET_DENSITY;
-    density_error * PRESSURE_MULTIPLIER
+pub fn density_to_pressure(density: f32, fluid: FluidKind, config: &SimConfig) -> f32 {
+    let pressure =
+        config
+            .equation_of_state
+            .equation()
+            .pressure(density, config.rest_density(fluid), config);
+    if config.clamp_negative_pressure {
+        // negative pressure pulls surface particles into clumps
+        pressure.max(0f32)
+    } else {
+        pressure
+    }
 }
 
-fn get_influence(a: &Vec2, b: &Vec2) -> f32 {
-    smoothing_kernel(a.distance(b.xy()))
+pub const INFLUENCE_MODIFIER: f32 = 10f32;
+
+// density of a particle inside fluid at rest, its neighbours sit on a square lattice of
+// `spacing` like freshly spawned ones
+pub fn lattice_density(spacing: f32, config: &SimConfig) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    let reach = (config.smoothing_distance / spacing) as i32;
+    let mut density = 0f32;
+    for x in -reach..=reach {
+        for y in -reach..=reach {
+            let distance = Vec2::new(x as f32, y as f32).length() * spacing;
+            density += kernel.value(distance, config.smoothing_distance) * INFLUENCE_MODIFIER;
+        }
+    }
+    density
 }
 
-pub const SMOOTHING_DISTANCE: u32 = 12;
-const INFLUENCE_MODIFIER: f32 = 10f32;
+// density and near density around `sample_particle_pos`. the density is the particle's own
+// mass times how many neighbours it has (Solenthaler and Pajarola 2008), so a light fluid
+// next to a heavy one keeps its own density instead of getting smeared into it
 pub fn sample_density(
     sample_particle_pos: &Vec2,
+    sample_fluid: FluidKind,
     sample_connected_cells: &[usize],
     particle_grid: &[Vec<usize>],
     particles: &[Vec2],
-) -> f32 {
+    config: &SimConfig,
+) -> (f32, f32) {
+    let kernel = config.kernels.density.kernel();
     let mut density: f32 = 0f32;
+    let mut near_density: f32 = 0f32;
     for cell in sample_connected_cells {
-        if cell == &usize::MAX {
+        if cell == &usize::MAX || cell >= &particle_grid.len() {
             continue;
         }
         for particle_index in &particle_grid[cell.to_owned()] {
-            let influence =
-                get_influence(sample_particle_pos, &particles[particle_index.to_owned()]);
+            let distance = sample_particle_pos.distance(particles[particle_index.to_owned()]);
+            let influence = kernel.value(distance, config.smoothing_distance);
             density += influence * INFLUENCE_MODIFIER;
+            near_density +=
+                NEAR_DENSITY_KERNEL.value(distance, config.smoothing_distance) * INFLUENCE_MODIFIER;
         }
     }
 
-    density
+    (
+        density * config.fluids.get(sample_fluid).relative_density,
+        near_density,
+    )
 }
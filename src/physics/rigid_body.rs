@@ -0,0 +1,768 @@
+use std::f32::consts::PI;
+
+use bevy::math::*;
+
+use crate::{
+    collisions::{closest_point_on_segment, particle_radius},
+    fluid_world::{FluidWorld, par_map_particles},
+    obstacles::Obstacle,
+    pressure_handler::INFLUENCE_MODIFIER,
+    scenario::{Body, Shape, Wall},
+    sim_config::SimConfig,
+    smoothing_kernels::KernelKind,
+};
+
+const CIRCLE_OUTLINE_SEGMENTS: usize = 32;
+// largest gap between the outline points walls and obstacles are tested with, in pixels
+const SURFACE_POINT_SPACING: f32 = 2f32;
+// boundary samples along the outline for every rest spacing of the particles
+const BOUNDARY_SAMPLES_PER_SPACING: f32 = 2f32;
+
+// outline in the body's own frame, centered on its center of mass
+#[derive(Clone)]
+pub enum BodyShape {
+    Circle { radius: f32 },
+    // convex and counter clockwise
+    Polygon { points: Vec<Vec2> },
+}
+
+// point on a body's outline standing in for the fluid the body keeps out (Akinci et al.
+// 2012). particles next to a body count the samples in their density, so the pressure
+// pushing them off the body is the one the fluid around has and the body feels it back
+#[derive(Clone, Copy)]
+pub struct BoundarySample {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    // how many particles the sample stands for, less where the samples crowd together
+    pub weight: f32,
+}
+
+// solid that floats in the fluid: the particles around push it with their pressure and
+// get pushed back
+#[derive(Clone)]
+pub struct RigidBody {
+    pub shape: BodyShape,
+    // of the center of mass
+    pub position: Vec2,
+    // radians, counter clockwise
+    pub angle: f32,
+    pub velocity: Vec2,
+    pub angular_velocity: f32,
+    pub mass: f32,
+    pub inertia: f32,
+    // mass of the water filling the body's area at rest
+    pub displaced_mass: f32,
+    bounding_radius: f32,
+    // boundary samples in the body's own frame and the spacing, smoothing distance and
+    // density kernel they were weighted for, every step only moves them with the body
+    local_samples: Vec<BoundarySample>,
+    samples_weighted_for: Option<(f32, f32, KernelKind)>,
+}
+
+impl RigidBody {
+    pub fn from_scenario(body: &Body, config: &SimConfig) -> RigidBody {
+        let (shape, position, area, inertia_per_mass) = match &body.shape {
+            Shape::Circle { center, radius } => (
+                BodyShape::Circle { radius: *radius },
+                *center,
+                PI * radius * radius,
+                radius * radius / 2f32,
+            ),
+            Shape::Rect { center, size } => {
+                let half = *size / 2f32;
+                let corners = [-half, vec2(half.x, -half.y), half, vec2(-half.x, half.y)];
+                polygon_properties(&corners.map(|corner| *center + corner))
+            }
+            Shape::Polygon { points } => polygon_properties(points),
+        };
+        // mass of water filling the same area at rest, one particle every spacing squared
+        let water_mass = area * config.fluids.water.mass() / config.particle_spacing.powi(2);
+        let mass = body.mass.unwrap_or(body.relative_density * water_mass);
+        let bounding_radius = match &shape {
+            BodyShape::Circle { radius } => *radius,
+            BodyShape::Polygon { points } => points
+                .iter()
+                .map(|point| point.length())
+                .fold(0f32, f32::max),
+        };
+        RigidBody {
+            shape,
+            position,
+            angle: body.angle.to_radians(),
+            velocity: body.velocity,
+            angular_velocity: body.angular_velocity.to_radians(),
+            mass,
+            inertia: mass * inertia_per_mass,
+            displaced_mass: water_mass,
+            bounding_radius,
+            local_samples: Vec::new(),
+            samples_weighted_for: None,
+        }
+    }
+
+    fn rotation(&self) -> Mat2 {
+        Mat2::from_angle(self.angle)
+    }
+
+    // closed outline in the body's own frame
+    fn local_outline(&self) -> Vec<Vec2> {
+        let mut outline: Vec<Vec2> = match &self.shape {
+            BodyShape::Circle { radius } => (0..CIRCLE_OUTLINE_SEGMENTS)
+                .map(|i| {
+                    Vec2::from_angle(i as f32 / CIRCLE_OUTLINE_SEGMENTS as f32 * 2f32 * PI)
+                        * *radius
+                })
+                .collect(),
+            BodyShape::Polygon { points } => points.clone(),
+        };
+        outline.push(outline[0]);
+        outline
+    }
+
+    // closed outline in world space, for drawing
+    pub fn outline(&self) -> Vec<Vec2> {
+        let rotation = self.rotation();
+        self.local_outline()
+            .iter()
+            .map(|point| self.position + rotation * *point)
+            .collect()
+    }
+
+    // closest point on the outline, the normal pointing out of the body there
+    // and the distance to it, negative inside
+    fn closest_surface_point(&self, point: Vec2) -> (Vec2, Vec2, f32) {
+        let rotation = self.rotation();
+        let local = rotation.transpose() * (point - self.position);
+        let (closest, normal, distance) = match &self.shape {
+            BodyShape::Circle { radius } => {
+                let normal = local.normalize_or(Vec2::Y);
+                (normal * *radius, normal, local.length() - radius)
+            }
+            BodyShape::Polygon { points } => closest_polygon_point(points, local),
+        };
+        (
+            self.position + rotation * closest,
+            rotation * normal,
+            distance,
+        )
+    }
+
+    // whether a particle at `point` is inside the body or closer to the outline than the
+    // half spacing particles rest at next to it
+    pub fn covers(&self, point: Vec2, config: &SimConfig) -> bool {
+        point.distance_squared(self.position)
+            < (self.bounding_radius + config.particle_spacing).powi(2)
+            && self.closest_surface_point(point).2 < config.particle_spacing / 2f32
+    }
+
+    // whether `point` is inside the outline
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.distance_squared(self.position) < self.bounding_radius.powi(2)
+            && self.closest_surface_point(point).2 < 0f32
+    }
+
+    // velocity of the body's material at `point`
+    pub fn point_velocity(&self, point: Vec2) -> Vec2 {
+        self.velocity + self.angular_velocity * (point - self.position).perp()
+    }
+
+    // applies `impulse` at `point` to the velocity and spin
+    pub fn apply_impulse(&mut self, impulse: Vec2, point: Vec2) {
+        self.velocity += impulse / self.mass;
+        self.angular_velocity += (point - self.position).perp_dot(impulse) / self.inertia;
+    }
+
+    // applies the `impulse` and `angular_impulse` the fluid pushed the body with over a step
+    // of `delta`, together with the gravity of that step. the water moving with the body
+    // (its added mass) takes its share, otherwise a body lighter than the water overreacts
+    // to the push, drags the fluid along harder in the next step and gets pushed back
+    // harder still
+    pub fn apply_fluid_impulse(
+        &mut self,
+        impulse: Vec2,
+        angular_impulse: f32,
+        gravity: Vec2,
+        delta: f32,
+    ) {
+        let share = self.mass / (self.mass + self.displaced_mass);
+        let gravity_impulse = gravity * self.mass * delta;
+        // step_bodies adds the whole gravity of the step afterwards
+        self.velocity += (impulse + gravity_impulse) / self.mass * share - gravity * delta;
+        self.angular_velocity += angular_impulse / self.inertia * share;
+    }
+
+    // impulse along `normal` that stops a contact at `point` from closing in, with
+    // `inverse_mass` of whatever the body hits
+    fn contact_impulse(
+        &self,
+        relative_normal_speed: f32,
+        point: Vec2,
+        normal: Vec2,
+        inverse_mass: f32,
+        restitution: f32,
+    ) -> f32 {
+        let arm = (point - self.position).perp_dot(normal);
+        -(1f32 + restitution) * relative_normal_speed
+            / (inverse_mass + 1f32 / self.mass + arm * arm / self.inertia)
+    }
+
+    // moves a particle that got inside back to the surface and trades momentum with it,
+    // returns the impulse the body gets back and where
+    fn push_particle(
+        &self,
+        position: &mut Vec2,
+        velocity: &mut Vec2,
+        particle_mass: f32,
+        config: &SimConfig,
+    ) -> Option<(Vec2, Vec2)> {
+        let radius = particle_radius();
+        if position.distance_squared(self.position) > (self.bounding_radius + radius).powi(2) {
+            return None;
+        }
+        let (closest, normal, distance) = self.closest_surface_point(*position);
+        if distance >= radius {
+            return None;
+        }
+        *position = closest + normal * radius;
+        let normal_speed = (*velocity - self.point_velocity(closest)).dot(normal);
+        if normal_speed >= 0f32 {
+            return None;
+        }
+        let impulse = self.contact_impulse(
+            normal_speed,
+            closest,
+            normal,
+            1f32 / particle_mass,
+            config.collision_damping,
+        );
+        *velocity += normal * impulse / particle_mass;
+        Some((-normal * impulse, closest))
+    }
+
+    // points along the outline in world space, at most `spacing` apart
+    fn outline_points(&self, spacing: f32) -> Vec<Vec2> {
+        points_along(&self.outline(), spacing)
+    }
+
+    // boundary samples along the outline where the body is now. the weights only change
+    // with the settings, so they are worked out once in the body's own frame
+    fn boundary_samples(&mut self, config: &SimConfig) -> Vec<BoundarySample> {
+        let settings = (
+            config.particle_spacing,
+            config.smoothing_distance,
+            config.kernels.density,
+        );
+        if self.samples_weighted_for != Some(settings) {
+            self.local_samples = self.weigh_local_samples(config);
+            self.samples_weighted_for = Some(settings);
+        }
+        let rotation = self.rotation();
+        self.local_samples
+            .iter()
+            .map(|sample| {
+                let position = self.position + rotation * sample.position;
+                BoundarySample {
+                    position,
+                    velocity: self.point_velocity(position),
+                    weight: sample.weight,
+                }
+            })
+            .collect()
+    }
+
+    // boundary samples along the outline in the body's own frame, weighted by the scale a
+    // straight outline needs over the kernel sum of their neighbouring samples
+    fn weigh_local_samples(&self, config: &SimConfig) -> Vec<BoundarySample> {
+        let kernel = config.kernels.density.kernel();
+        let h = config.smoothing_distance;
+        let weight_scale = boundary_weight_scale(config);
+        let points = points_along(
+            &self.local_outline(),
+            config.particle_spacing / BOUNDARY_SAMPLES_PER_SPACING,
+        );
+        points
+            .iter()
+            .map(|point| {
+                let kernel_sum: f32 = points
+                    .iter()
+                    .map(|other| kernel.value(point.distance(*other), h))
+                    .sum();
+                BoundarySample {
+                    position: *point,
+                    velocity: Vec2::ZERO,
+                    weight: weight_scale / kernel_sum,
+                }
+            })
+            .collect()
+    }
+
+    // extreme points in every direction along the container sides
+    fn support_points(&self) -> Vec<Vec2> {
+        match &self.shape {
+            BodyShape::Circle { radius } => [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y]
+                .map(|direction| self.position + direction * *radius)
+                .to_vec(),
+            BodyShape::Polygon { points } => points
+                .iter()
+                .map(|point| self.position + self.rotation() * *point)
+                .collect(),
+        }
+    }
+
+    // points along the outline in world space, close enough together that no wall or
+    // obstacle corner slips between two of them
+    fn surface_points(&self) -> Vec<Vec2> {
+        self.outline_points(SURFACE_POINT_SPACING)
+    }
+
+    // moves the body `depth` along `normal` out of something that doesn't move and
+    // bounces it off at `point`
+    fn resolve_static_contact(&mut self, point: Vec2, normal: Vec2, depth: f32, restitution: f32) {
+        self.position += normal * depth;
+        let point = point + normal * depth;
+        let normal_speed = self.point_velocity(point).dot(normal);
+        if normal_speed < 0f32 {
+            let impulse = self.contact_impulse(normal_speed, point, normal, 0f32, restitution);
+            self.apply_impulse(normal * impulse, point);
+        }
+    }
+
+    // bounces off the container sides like the particles, deepest point first
+    fn collide_with_container(&mut self, config: &SimConfig) {
+        let half_size = config.box_size / 2f32;
+        for normal in [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y] {
+            // `normal` points into the container from the side at `-normal * half_size`
+            let Some((point, depth)) = self
+                .support_points()
+                .into_iter()
+                .map(|point| (point, -(point.dot(normal) + half_size.dot(normal.abs()))))
+                .filter(|(_, depth)| *depth > 0f32)
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+            else {
+                continue;
+            };
+            self.resolve_static_contact(point, normal, depth, config.collision_damping);
+        }
+    }
+
+    // walls are capsules as thick as the wall, each one pushes on the deepest outline point
+    // inside it, or on the body around one of its ends when that pokes in between them
+    fn collide_with_walls(&mut self, walls: &[Wall], config: &SimConfig) {
+        for wall in walls {
+            let radius = wall.thickness / 2f32;
+            let closest_to_center = closest_point_on_segment(self.position, wall.start, wall.end);
+            if self.position.distance(closest_to_center) >= self.bounding_radius + radius {
+                continue;
+            }
+            let outline_contacts = self.surface_points().into_iter().filter_map(|point| {
+                let offset = point - closest_point_on_segment(point, wall.start, wall.end);
+                let distance = offset.length();
+                (distance < radius).then(|| {
+                    let normal = if distance > 0f32 {
+                        offset / distance
+                    } else {
+                        (point - self.position).normalize_or(Vec2::Y)
+                    };
+                    (point, normal, radius - distance)
+                })
+            });
+            let end_contacts = [wall.start, wall.end].into_iter().filter_map(|end| {
+                let (closest, normal, distance) = self.closest_surface_point(end);
+                (distance < radius).then_some((closest, -normal, radius - distance))
+            });
+            let deepest = outline_contacts
+                .chain(end_contacts)
+                .max_by(|a, b| a.2.total_cmp(&b.2));
+            if let Some((point, normal, depth)) = deepest {
+                self.resolve_static_contact(point, normal, depth, config.collision_damping);
+            }
+        }
+    }
+
+    // deepest outline point inside `other`, the normal pointing out of `other` there and
+    // how deep the point is
+    fn deepest_point_inside(&self, other: &RigidBody) -> Option<(Vec2, Vec2, f32)> {
+        self.surface_points()
+            .into_iter()
+            .map(|point| {
+                let (_, normal, distance) = other.closest_surface_point(point);
+                (point, normal, -distance)
+            })
+            .filter(|(_, _, depth)| *depth > 0f32)
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    // pushed out of every obstacle along its normal at the deepest outline point inside
+    fn collide_with_obstacles(&mut self, obstacles: &[Obstacle]) {
+        for obstacle in obstacles {
+            if obstacle.shape.distance(self.position) >= self.bounding_radius {
+                continue;
+            }
+            let deepest = self
+                .surface_points()
+                .into_iter()
+                .map(|point| (point, obstacle.shape.distance(point)))
+                .filter(|(_, distance)| *distance < 0f32)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((point, distance)) = deepest {
+                let normal = obstacle.shape.normal(point);
+                self.resolve_static_contact(point, normal, -distance, obstacle.restitution);
+            }
+        }
+    }
+}
+
+// two bodies are pushed apart at the deepest outline point of one inside the other,
+// the lighter one moves more
+fn collide_bodies(a: &mut RigidBody, b: &mut RigidBody, restitution: f32) {
+    if a.position.distance(b.position) >= a.bounding_radius + b.bounding_radius {
+        return;
+    }
+    // the normal points from `b` to `a`
+    let b_in_a = b
+        .deepest_point_inside(a)
+        .map(|(point, normal, depth)| (point, -normal, depth));
+    let Some((point, normal, depth)) = a
+        .deepest_point_inside(b)
+        .into_iter()
+        .chain(b_in_a)
+        .max_by(|first, second| first.2.total_cmp(&second.2))
+    else {
+        return;
+    };
+    let share = b.mass / (a.mass + b.mass);
+    a.position += normal * depth * share;
+    b.position -= normal * depth * (1f32 - share);
+    let normal_speed = (a.point_velocity(point) - b.point_velocity(point)).dot(normal);
+    if normal_speed < 0f32 {
+        let arm = (point - b.position).perp_dot(normal);
+        let inverse_mass = 1f32 / b.mass + arm * arm / b.inertia;
+        let impulse = a.contact_impulse(normal_speed, point, normal, inverse_mass, restitution);
+        a.apply_impulse(normal * impulse, point);
+        b.apply_impulse(-normal * impulse, point);
+    }
+}
+
+// weight scale of the boundary samples: a particle resting half a rest spacing off a straight
+// outline gets the density the particles on the other side would give it, like the mirror
+// images along the container sides
+fn boundary_weight_scale(config: &SimConfig) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    let h = config.smoothing_distance;
+    let spacing = config.particle_spacing;
+    let sample_spacing = spacing / BOUNDARY_SAMPLES_PER_SPACING;
+    let reach = (h / spacing) as i32;
+    // the particle's row and every row between it and the fluid further away
+    let mut fluid_density = 0f32;
+    for x in -reach..=reach {
+        for y in 0..=reach {
+            let distance = vec2(x as f32, y as f32).length() * spacing;
+            fluid_density += kernel.value(distance, h) * INFLUENCE_MODIFIER;
+        }
+    }
+    let sample_reach = (h / sample_spacing) as i32;
+    let (mut particle_sum, mut sample_sum) = (0f32, 0f32);
+    for i in -sample_reach..=sample_reach {
+        let along = i as f32 * sample_spacing;
+        particle_sum += kernel.value(vec2(along, spacing / 2f32).length(), h);
+        sample_sum += kernel.value(along.abs(), h);
+    }
+    (config.water_rest_density - fluid_density).max(0f32) * sample_sum
+        / (particle_sum * INFLUENCE_MODIFIER)
+}
+
+// boundary samples of every body where it is now
+pub fn boundary_samples(bodies: &mut [RigidBody], config: &SimConfig) -> Vec<Vec<BoundarySample>> {
+    bodies
+        .iter_mut()
+        .map(|body| body.boundary_samples(config))
+        .collect()
+}
+
+// boundary samples of every body closer than the smoothing distance to `point`,
+// with the index of their body
+pub fn boundary_neighbours<'a>(
+    world: &'a FluidWorld,
+    point: Vec2,
+    config: &SimConfig,
+) -> impl Iterator<Item = (usize, &'a BoundarySample)> + 'a {
+    let h = config.smoothing_distance;
+    world
+        .bodies
+        .iter()
+        .zip(&world.body_samples)
+        .enumerate()
+        .filter(move |(_, (body, _))| {
+            point.distance_squared(body.position) < (body.bounding_radius + h).powi(2)
+        })
+        .flat_map(|(index, (_, samples))| samples.iter().map(move |sample| (index, sample)))
+        .filter(move |(_, sample)| point.distance_squared(sample.position) < h * h)
+}
+
+// neighbour count the bodies add to the density at `point`
+pub fn boundary_density(world: &FluidWorld, point: Vec2, config: &SimConfig) -> f32 {
+    let kernel = config.kernels.density.kernel();
+    boundary_neighbours(world, point, config)
+        .map(|(_, sample)| {
+            kernel.value(point.distance(sample.position), config.smoothing_distance)
+                * sample.weight
+                * INFLUENCE_MODIFIER
+        })
+        .sum()
+}
+
+// sum of the density kernel gradients of the boundary samples around `point`,
+// weighted like the particles they stand for
+pub fn boundary_gradient(world: &FluidWorld, point: Vec2, config: &SimConfig) -> Vec2 {
+    let kernel = config.kernels.density.kernel();
+    boundary_neighbours(world, point, config)
+        .map(|(_, sample)| {
+            kernel.gradient(point - sample.position, config.smoothing_distance) * sample.weight
+        })
+        .sum()
+}
+
+// acceleration of a particle at `point` pushed off the bodies by `pressure_term`, what the
+// solver accelerates it with for every unit of pressure kernel gradient. the samples have no
+// pressure of their own, only the particle's pushes (Akinci et al. 2012). bodies only push,
+// a negative pressure would glue the fluid to them
+pub fn boundary_acceleration(
+    world: &FluidWorld,
+    point: Vec2,
+    pressure_term: f32,
+    config: &SimConfig,
+) -> Vec2 {
+    if pressure_term <= 0f32 {
+        return Vec2::ZERO;
+    }
+    let kernel = config.kernels.pressure.kernel();
+    boundary_neighbours(world, point, config)
+        .map(|(_, sample)| {
+            -pressure_term
+                * sample.weight
+                * kernel.gradient(point - sample.position, config.smoothing_distance)
+        })
+        .sum()
+}
+
+// pushes every body back as hard as the solver pushed the particles off it,
+// at the positions the neighbourhoods were built at
+fn push_bodies_back(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    let h = config.smoothing_distance;
+    let impulses = par_map_particles(world.body_pressure_terms.len(), |i| {
+        let pressure_term = world.body_pressure_terms[i];
+        if pressure_term <= 0f32 {
+            return Vec::new();
+        }
+        let kernel = config.kernels.pressure.kernel();
+        let position = world.predicted_positions[i];
+        boundary_neighbours(world, position, config)
+            .map(|(body, sample)| {
+                let acceleration =
+                    -pressure_term * sample.weight * kernel.gradient(position - sample.position, h);
+                (
+                    body,
+                    -acceleration * world.masses[i] * delta,
+                    sample.position,
+                )
+            })
+            .collect()
+    });
+    for (body, impulse, point) in impulses.into_iter().flatten() {
+        world.bodies[body].apply_impulse(impulse, point);
+    }
+}
+
+// pushes the bodies back from the fluid pressure and moves them with gravity, then every
+// particle that still got inside pushes on it and gets pushed out. runs after the solver,
+// so the particles already made their move
+pub fn step_bodies(world: &mut FluidWorld, config: &SimConfig, delta: f32) {
+    if world.bodies.is_empty() {
+        return;
+    }
+    push_bodies_back(world, config, delta);
+    for body in &mut world.bodies {
+        body.velocity += config.gravity * delta;
+        body.position += body.velocity * delta;
+        body.angle += body.angular_velocity * delta;
+    }
+    // every particle is pushed out of the bodies where they are after the move,
+    // the bodies take the impulses back once all particles are done
+    let pushed = par_map_particles(world.len(), |i| {
+        let (mut position, mut velocity) = (world.positions[i], world.velocities[i]);
+        let impulses: Vec<(usize, Vec2, Vec2)> = world
+            .bodies
+            .iter()
+            .enumerate()
+            .filter_map(|(index, body)| {
+                body.push_particle(&mut position, &mut velocity, world.masses[i], config)
+                    .map(|(impulse, point)| (index, impulse, point))
+            })
+            .collect();
+        (position, velocity, impulses)
+    });
+    for (i, (position, velocity, impulses)) in pushed.into_iter().enumerate() {
+        world.positions[i] = position;
+        world.velocities[i] = velocity;
+        for (body, impulse, point) in impulses {
+            world.bodies[body].apply_impulse(impulse, point);
+        }
+    }
+    for i in 0..world.bodies.len() {
+        let (first, rest) = world.bodies.split_at_mut(i + 1);
+        for other in rest {
+            collide_bodies(&mut first[i], other, config.collision_damping);
+        }
+    }
+    for body in &mut world.bodies {
+        body.collide_with_walls(&world.walls, config);
+        body.collide_with_obstacles(&world.obstacles);
+        body.collide_with_container(config);
+    }
+}
+
+// points along a closed `outline`, at most `spacing` apart
+fn points_along(outline: &[Vec2], spacing: f32) -> Vec<Vec2> {
+    outline
+        .windows(2)
+        .flat_map(|edge| {
+            let steps = (edge[0].distance(edge[1]) / spacing).ceil().max(1f32);
+            (0..steps as usize).map(move |i| edge[0].lerp(edge[1], i as f32 / steps))
+        })
+        .collect()
+}
+
+// polygon around its centroid made counter clockwise, its centroid, area and
+// moment of inertia per mass
+fn polygon_properties(points: &[Vec2]) -> (BodyShape, Vec2, f32, f32) {
+    let edges = || (0..points.len()).map(|i| (points[i], points[(i + 1) % points.len()]));
+    let double_area: f32 = edges().map(|(a, b)| a.perp_dot(b)).sum();
+    let centroid =
+        edges().map(|(a, b)| (a + b) * a.perp_dot(b)).sum::<Vec2>() / (3f32 * double_area);
+    let mut local: Vec<Vec2> = points.iter().map(|point| *point - centroid).collect();
+    if double_area < 0f32 {
+        local.reverse();
+    }
+    let local_edges = (0..local.len()).map(|i| (local[i], local[(i + 1) % local.len()]));
+    let (weighted, cross_sum) = local_edges.fold((0f32, 0f32), |(weighted, cross_sum), (a, b)| {
+        let cross = a.perp_dot(b);
+        (
+            weighted + cross * (a.dot(a) + a.dot(b) + b.dot(b)),
+            cross_sum + cross,
+        )
+    });
+    (
+        BodyShape::Polygon { points: local },
+        centroid,
+        double_area.abs() / 2f32,
+        weighted / (6f32 * cross_sum),
+    )
+}
+
+// same as `RigidBody::closest_surface_point` in the polygon's own frame
+fn closest_polygon_point(points: &[Vec2], point: Vec2) -> (Vec2, Vec2, f32) {
+    let mut inside = true;
+    let mut best = (points[0], Vec2::Y, f32::MAX);
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let edge = b - a;
+        // counter clockwise, so the outside is on the right of every edge
+        let outward = vec2(edge.y, -edge.x).normalize_or(Vec2::Y);
+        if (point - a).dot(outward) > 0f32 {
+            inside = false;
+        }
+        let closest = closest_point_on_segment(point, a, b);
+        let distance = point.distance(closest);
+        if distance < best.2 {
+            let normal = if distance > 0f32 {
+                (point - closest) / distance
+            } else {
+                outward
+            };
+            best = (closest, normal, distance);
+        }
+    }
+    let (closest, normal, distance) = best;
+    if inside {
+        // the direction to the closest point leads out of the polygon
+        let normal = if distance > 0f32 { -normal } else { normal };
+        (closest, normal, -distance)
+    } else {
+        (closest, normal, distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{particles_spawning, scenario::Scenario};
+
+    fn small_container() -> SimConfig {
+        SimConfig {
+            box_size: vec2(160f32, 100f32),
+            ..SimConfig::default()
+        }
+    }
+
+    #[test]
+    fn bodies_rest_on_each_other() {
+        let config = small_container();
+        let scenario: Scenario = toml::from_str(
+            r#"
+[[body]]
+shape = { type = "rect", center = [0.0, -40.0], size = [40.0, 10.0] }
+
+[[body]]
+shape = { type = "circle", center = [0.0, -10.0], radius = 5.0 }
+"#,
+        )
+        .unwrap();
+        let mut world = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        // no particles, only the bodies move
+        for _ in 0..300 {
+            step_bodies(&mut world, &config, config.physics_step);
+        }
+        let (slab, ball) = (&world.bodies[0], &world.bodies[1]);
+        // the slab lies on the floor and the ball on top of it
+        assert!(
+            (slab.position.y - -45f32).abs() < 1f32,
+            "{}",
+            slab.position.y
+        );
+        assert!(
+            (ball.position.y - -35f32).abs() < 1f32,
+            "{}",
+            ball.position.y
+        );
+    }
+
+    // buoyancy comes from the particle pressure alone, so this checks the fluid pushes the
+    // bodies as hard as the water they displace weighs
+    #[test]
+    fn light_body_floats_and_heavy_body_sinks() {
+        let config = small_container();
+        let scenario: Scenario = toml::from_str(
+            r#"
+[[fluid]]
+shape = { type = "rect", center = [0.0, -25.0], size = [150.0, 48.0] }
+
+[[body]]
+shape = { type = "circle", center = [-40.0, -30.0], radius = 10.0 }
+relative_density = 0.3
+
+[[body]]
+shape = { type = "circle", center = [40.0, -10.0], radius = 10.0 }
+relative_density = 3.0
+"#,
+        )
+        .unwrap();
+        let mut world = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        for _ in 0..300 {
+            world.step(&config, config.physics_step);
+        }
+        let (light, heavy) = (&world.bodies[0], &world.bodies[1]);
+        // the light one comes up to the surface, the heavy one lies on the floor
+        assert!(light.position.y > -10f32, "{}", light.position.y);
+        assert!(heavy.position.y < -38f32, "{}", heavy.position.y);
+    }
+}
@@ -0,0 +1,191 @@
+use bevy::{math::*, prelude::Resource};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounding_box::BOX_BOUNDS_SIZE_PIXELS,
+    equation_of_state::EquationOfStateKind,
+    flip::FlipConfig,
+    fluid_kind::{FluidKind, FluidTable},
+    heat::HeatConfig,
+    integrators::IntegratorKind,
+    particles_spawning::PARTICLES_SPACING,
+    pbf::PbfConfig,
+    pressure_handler,
+    smoothing_kernels::KernelSelection,
+};
+
+// how pressure is computed every step
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SolverKind {
+    // weakly compressible, pressure straight from the equation of state
+    #[default]
+    Wcsph,
+    // predictive-corrective, iterates pressure until the fluid is incompressible enough
+    Pcisph,
+    // divergence-free, corrects velocities so they neither compress the fluid nor let
+    // its density drift, stays incompressible with larger steps
+    Dfsph,
+    // position based, projects density constraints on predicted positions, stable with
+    // large steps at the cost of some accuracy
+    Pbf,
+    // hybrid particle and grid, pressure is solved on a grid instead of between particles
+    Flip,
+}
+
+// stopping rules of the iterative pressure solvers
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PressureSolveConfig {
+    // largest relative compression that is good enough, 0.01 is 1%
+    pub max_density_error: f32,
+    // largest relative compression the velocities may still cause in one step (dfsph, flip)
+    pub max_divergence_error: f32,
+    pub min_iterations: u32,
+    pub max_iterations: u32,
+}
+
+impl Default for PressureSolveConfig {
+    fn default() -> Self {
+        PressureSolveConfig {
+            max_density_error: 0.01f32,
+            max_divergence_error: 0.01f32,
+            min_iterations: 3,
+            max_iterations: 50,
+        }
+    }
+}
+
+// everything the solver reads while stepping, can be changed while running
+#[derive(Resource, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    pub gravity: Vec2,
+    // simulated seconds per real second
+    pub time_scale: f32,
+    // simulated seconds of one physics step, frames run as many steps as fit in their time
+    pub physics_step: f32,
+    // a slow frame runs at most this many steps and drops the rest of its time
+    pub max_steps_per_frame: u32,
+    // picks every step from the particle speeds and forces instead, `physics_step` is the largest
+    pub adaptive_time_step: bool,
+    // fraction of the smoothing distance the fastest particle may travel in one adaptive step
+    pub cfl_number: f32,
+    pub min_physics_step: f32,
+    pub integrator: IntegratorKind,
+    pub solver: SolverKind,
+    pub pressure_solve: PressureSolveConfig,
+    pub pbf: PbfConfig,
+    pub flip: FlipConfig,
+    pub equation_of_state: EquationOfStateKind,
+    pub clamp_negative_pressure: bool,
+    // stiffness of the equation of state
+    pub pressure_multiplier: f32,
+    pub pressure_force_modifier: f32,
+    // stiffness of the near pressure that keeps particles from stacking, 0 turns it off
+    pub near_pressure_multiplier: f32,
+    // how fast neighbours even out their velocities, per second of simulated time
+    pub viscosity_strength: f32,
+    pub fluids: FluidTable,
+    // strength of the cohesion and curvature forces along the fluid surface, 0 turns it off
+    pub surface_tension: f32,
+    pub heat: HeatConfig,
+    pub collision_damping: f32,
+    pub smoothing_distance: f32,
+    pub air_density: f32,
+    pub particle_drag_coefficient: f32,
+    pub kernels: KernelSelection,
+    // seeded spawning and a fixed frame delta, two runs of the same setup end up bit-identical
+    pub deterministic: bool,
+    pub seed: u64,
+    // real seconds of every frame when running deterministic
+    pub fixed_delta: f32,
+    // comes from the [container] section of the settings file
+    #[serde(skip)]
+    pub box_size: Vec2,
+    // distance between the particles of a fluid at rest, comes from the [spawning] section
+    #[serde(skip)]
+    pub particle_spacing: f32,
+    // density of water at rest, the other fluids are relative to it.
+    // set from the density kernel and `particle_spacing` by `with_rest_density`
+    #[serde(skip)]
+    pub water_rest_density: f32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            gravity: Vec2::new(0f32, -15f32),
+            time_scale: 2f32,
+            physics_step: 1f32 / 90f32,
+            max_steps_per_frame: 8,
+            adaptive_time_step: false,
+            cfl_number: 0.4f32,
+            min_physics_step: 0.0001f32,
+            integrator: IntegratorKind::SymplecticEuler,
+            solver: SolverKind::Wcsph,
+            pressure_solve: PressureSolveConfig::default(),
+            pbf: PbfConfig::default(),
+            flip: FlipConfig::default(),
+            equation_of_state: EquationOfStateKind::Linear,
+            clamp_negative_pressure: false,
+            pressure_multiplier: 100000f32,
+            pressure_force_modifier: 0.25f32,
+            near_pressure_multiplier: 0f32,
+            viscosity_strength: 30f32,
+            fluids: FluidTable::default(),
+            surface_tension: 0f32,
+            heat: HeatConfig::default(),
+            collision_damping: 0.5f32,
+            smoothing_distance: 12f32,
+            air_density: 1f32,
+            particle_drag_coefficient: 0.01f32,
+            kernels: KernelSelection::default(),
+            deterministic: false,
+            seed: 0,
+            fixed_delta: 1f32 / 60f32,
+            box_size: BOX_BOUNDS_SIZE_PIXELS,
+            particle_spacing: PARTICLES_SPACING,
+            water_rest_density: 0f32,
+        }
+        .with_rest_density()
+    }
+}
+
+impl SimConfig {
+    // simulated seconds a frame that took `frame_delta` real seconds should advance
+    pub fn frame_delta(&self, frame_delta: f32) -> f32 {
+        let frame_delta = if self.deterministic {
+            self.fixed_delta
+        } else {
+            frame_delta
+        };
+        frame_delta * self.time_scale
+    }
+
+    // has to be called again after changing the kernels, smoothing distance or spacing
+    pub fn with_rest_density(self) -> SimConfig {
+        SimConfig {
+            water_rest_density: pressure_handler::lattice_density(self.particle_spacing, &self),
+            ..self
+        }
+    }
+
+    pub fn rest_density(&self, fluid: FluidKind) -> f32 {
+        self.water_rest_density * self.fluids.get(fluid).relative_density
+    }
+
+    pub fn viscosity(&self, fluid: FluidKind) -> f32 {
+        self.viscosity_strength * self.fluids.get(fluid).relative_viscosity
+    }
+
+    // one grid cell is as big as the smoothing distance so neighbours are always in connected cells
+    pub fn grid_size(&self) -> Vec2 {
+        (self.box_size / self.smoothing_distance).ceil()
+    }
+
+    pub fn total_grid_size(&self) -> usize {
+        let grid_size = self.grid_size();
+        (grid_size.x as usize) * (grid_size.y as usize + 1) + 1
+    }
+}
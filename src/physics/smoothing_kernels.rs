@@ -0,0 +1,301 @@
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// radial smoothing kernel in 2D with support radius `h`,
+// every kernel is normalized so it integrates to 1 over its support
+pub trait SmoothingKernel {
+    fn normalization(&self, h: f32) -> f32;
+    fn value(&self, distance: f32, h: f32) -> f32;
+    // slope along the distance, negative for every kernel here
+    fn derivative(&self, distance: f32, h: f32) -> f32;
+    fn laplacian(&self, distance: f32, h: f32) -> f32;
+
+    // `offset` goes from the neighbour to the sample point
+    fn gradient(&self, offset: Vec2, h: f32) -> Vec2 {
+        let distance = offset.length();
+        if distance == 0f32 {
+            return Vec2::ZERO;
+        }
+        offset / distance * self.derivative(distance, h)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelKind {
+    Quadratic,
+    Poly6,
+    Spiky,
+    CubicSpline,
+    Wendland,
+}
+
+impl KernelKind {
+    pub fn kernel(&self) -> &'static dyn SmoothingKernel {
+        match self {
+            KernelKind::Quadratic => &Quadratic,
+            KernelKind::Poly6 => &Poly6,
+            KernelKind::Spiky => &Spiky,
+            KernelKind::CubicSpline => &CubicSpline,
+            KernelKind::Wendland => &Wendland,
+        }
+    }
+}
+
+// kernel used by every force, each one can pick the shape that suits it
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KernelSelection {
+    pub density: KernelKind,
+    pub pressure: KernelKind,
+    pub viscosity: KernelKind,
+}
+
+impl Default for KernelSelection {
+    fn default() -> Self {
+        KernelSelection {
+            density: KernelKind::Quadratic,
+            pressure: KernelKind::Quadratic,
+            viscosity: KernelKind::Poly6,
+        }
+    }
+}
+
+// laplacian of a radial function in 2D, at the center it's only
+// meaningful for kernels with a flat top like poly6
+fn radial_laplacian(second_derivative: f32, derivative: f32, distance: f32) -> f32 {
+    if distance == 0f32 {
+        return 2f32 * second_derivative;
+    }
+    second_derivative + derivative / distance
+}
+
+// (h - r)^2, the kernel the simulation started with
+pub struct Quadratic;
+
+impl SmoothingKernel for Quadratic {
+    fn normalization(&self, h: f32) -> f32 {
+        6f32 / (PI * h.powi(4))
+    }
+    fn value(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        (h - distance).powi(2) * self.normalization(h)
+    }
+    fn derivative(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        -2f32 * (h - distance) * self.normalization(h)
+    }
+    fn laplacian(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        let second_derivative = 2f32 * self.normalization(h);
+        radial_laplacian(second_derivative, self.derivative(distance, h), distance)
+    }
+}
+
+// (h^2 - r^2)^3, smooth everywhere, good for density and viscosity
+pub struct Poly6;
+
+impl SmoothingKernel for Poly6 {
+    fn normalization(&self, h: f32) -> f32 {
+        4f32 / (PI * h.powi(8))
+    }
+    fn value(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        (h * h - distance * distance).powi(3) * self.normalization(h)
+    }
+    fn derivative(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        -6f32 * distance * (h * h - distance * distance).powi(2) * self.normalization(h)
+    }
+    fn laplacian(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        let r2 = distance * distance;
+        -12f32 * (h * h - r2) * (h * h - 3f32 * r2) * self.normalization(h)
+    }
+}
+
+// (h - r)^3, gradient doesn't vanish near the center so close particles keep pushing apart
+pub struct Spiky;
+
+impl SmoothingKernel for Spiky {
+    fn normalization(&self, h: f32) -> f32 {
+        10f32 / (PI * h.powi(5))
+    }
+    fn value(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        (h - distance).powi(3) * self.normalization(h)
+    }
+    fn derivative(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        -3f32 * (h - distance).powi(2) * self.normalization(h)
+    }
+    fn laplacian(&self, distance: f32, h: f32) -> f32 {
+        if distance >= h {
+            return 0f32;
+        }
+        let second_derivative = 6f32 * (h - distance) * self.normalization(h);
+        radial_laplacian(second_derivative, self.derivative(distance, h), distance)
+    }
+}
+
+// Monaghan's M4 B-spline with q = r / h
+pub struct CubicSpline;
+
+impl SmoothingKernel for CubicSpline {
+    fn normalization(&self, h: f32) -> f32 {
+        40f32 / (7f32 * PI * h * h)
+    }
+    fn value(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        let value = if q <= 0.5f32 {
+            6f32 * (q * q * q - q * q) + 1f32
+        } else if q < 1f32 {
+            2f32 * (1f32 - q).powi(3)
+        } else {
+            0f32
+        };
+        value * self.normalization(h)
+    }
+    fn derivative(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        let derivative = if q <= 0.5f32 {
+            6f32 * (3f32 * q * q - 2f32 * q)
+        } else if q < 1f32 {
+            -6f32 * (1f32 - q).powi(2)
+        } else {
+            0f32
+        };
+        derivative * self.normalization(h) / h
+    }
+    fn laplacian(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        let second_derivative = if q <= 0.5f32 {
+            6f32 * (6f32 * q - 2f32)
+        } else if q < 1f32 {
+            12f32 * (1f32 - q)
+        } else {
+            return 0f32;
+        };
+        radial_laplacian(
+            second_derivative * self.normalization(h) / (h * h),
+            self.derivative(distance, h),
+            distance,
+        )
+    }
+}
+
+// Wendland C2, no pairing instability even with many neighbours
+pub struct Wendland;
+
+impl SmoothingKernel for Wendland {
+    fn normalization(&self, h: f32) -> f32 {
+        7f32 / (PI * h * h)
+    }
+    fn value(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        if q >= 1f32 {
+            return 0f32;
+        }
+        (1f32 - q).powi(4) * (1f32 + 4f32 * q) * self.normalization(h)
+    }
+    fn derivative(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        if q >= 1f32 {
+            return 0f32;
+        }
+        -20f32 * q * (1f32 - q).powi(3) * self.normalization(h) / h
+    }
+    fn laplacian(&self, distance: f32, h: f32) -> f32 {
+        let q = distance / h;
+        if q >= 1f32 {
+            return 0f32;
+        }
+        let second_derivative =
+            20f32 * (1f32 - q).powi(2) * (4f32 * q - 1f32) * self.normalization(h) / (h * h);
+        radial_laplacian(second_derivative, self.derivative(distance, h), distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every kernel has to integrate to 1 over the plane, or densities depend on the kernel
+    #[test]
+    fn kernels_are_normalized() {
+        let h = 12f32;
+        let steps = 10000;
+        let dr = 2f32 * h / steps as f32;
+        for kind in [
+            KernelKind::Quadratic,
+            KernelKind::Poly6,
+            KernelKind::Spiky,
+            KernelKind::CubicSpline,
+            KernelKind::Wendland,
+        ] {
+            let kernel = kind.kernel();
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let r = (i as f32 + 0.5f32) * dr;
+                    kernel.value(r, h) * 2f32 * PI * r * dr
+                })
+                .sum();
+            assert!(
+                (integral - 1f32).abs() < 1e-3,
+                "{:?} integrates to {}",
+                kind,
+                integral
+            );
+        }
+    }
+
+    // laplacian is the second derivative plus the slope over the distance, checked against
+    // central differences of the slope
+    #[test]
+    fn laplacians_match_the_derivatives() {
+        let h = 12f32;
+        let step = 1e-2f32;
+        for kind in [
+            KernelKind::Quadratic,
+            KernelKind::Poly6,
+            KernelKind::Spiky,
+            KernelKind::CubicSpline,
+            KernelKind::Wendland,
+        ] {
+            let kernel = kind.kernel();
+            for distance in [1f32, 3f32, 5f32, 7.5f32, 10f32, 11.5f32] {
+                let second_derivative = (kernel.derivative(distance + step, h)
+                    - kernel.derivative(distance - step, h))
+                    / (2f32 * step);
+                let expected = second_derivative + kernel.derivative(distance, h) / distance;
+                let laplacian = kernel.laplacian(distance, h);
+                assert!(
+                    (laplacian - expected).abs() <= 1e-3 * expected.abs().max(1e-5),
+                    "{:?} laplacian at {} is {}, expected {}",
+                    kind,
+                    distance,
+                    laplacian,
+                    expected
+                );
+            }
+            assert_eq!(kernel.laplacian(h, h), 0f32);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+
+use bevy::math::Vec2;
+
+use crate::{
+    fluid_kind::FluidKind,
+    fluid_world::{FluidWorld, par_map_particles},
+    pressure_handler::INFLUENCE_MODIFIER,
+    sim_config::SimConfig,
+};
+
+// surface tension of Akinci et al. 2013: cohesion pulls neighbours together and a curvature
+// term evens out the surface normals, together they bead droplets and round off sheets
+// without the fluid clumping along its surface
+
+// color field gradient scaled by the smoothing distance, about 0 inside the fluid and
+// pointing into it along the surface
+pub fn calculate_surface_normals(
+    particles_gird: &[Vec<usize>],
+    particles_pos: &[Vec2],
+    connected_cells: &[usize],
+    densities: &[f32],
+    fluid_kinds: &[FluidKind],
+    config: &SimConfig,
+) -> Vec<Vec2> {
+    par_map_particles(particles_pos.len(), |sample_index| {
+        let kernel = config.kernels.density.kernel();
+        let sample_point = particles_pos[sample_index];
+        let mut normal = Vec2::ZERO;
+        for cell in &connected_cells[sample_index * 9..(sample_index + 1) * 9] {
+            if cell == &usize::MAX || cell >= &particles_gird.len() {
+                continue;
+            }
+            for particle_index in &particles_gird[cell.to_owned()] {
+                let particle_index = particle_index.to_owned();
+                let offset = sample_point - particles_pos[particle_index];
+                let volume = INFLUENCE_MODIFIER
+                    * config
+                        .fluids
+                        .get(fluid_kinds[particle_index])
+                        .relative_density
+                    / densities[particle_index];
+                normal += kernel.gradient(offset, config.smoothing_distance) * volume;
+            }
+        }
+        normal * config.smoothing_distance
+    })
+}
+
+// acceleration, `config.surface_tension` is its strength in px/s^2
+pub fn calculate_surface_tension(
+    world: &FluidWorld,
+    sample_particle_index: usize,
+    config: &SimConfig,
+) -> Vec2 {
+    let (particles_pos, particle_grid) = (&world.predicted_positions, &world.grid);
+    let (densities, normals) = (&world.densities, &world.surface_normals);
+    let fluid_kinds = &world.fluid_kinds;
+    let sample_point = particles_pos[sample_particle_index];
+    // every particle takes up the same room at rest whatever its fluid, like in the normals
+    let rest_volume = INFLUENCE_MODIFIER / config.water_rest_density;
+    // share of the neighbourhood every neighbour stands for, averages the curvature term
+    let neighbour_share = rest_volume / (PI * config.smoothing_distance.powi(2));
+    let mut acceleration = Vec2::ZERO;
+    for cell in world.particle_connected_cells(sample_particle_index) {
+        if cell == &usize::MAX || cell >= &particle_grid.len() {
+            continue;
+        }
+        for particle_index_ref in &particle_grid[cell.to_owned()] {
+            let particle_index = particle_index_ref.to_owned();
+            let pos = particles_pos[particle_index];
+            if particle_index == sample_particle_index || sample_point == pos {
+                continue;
+            }
+
+            let dist = pos.distance(sample_point);
+            // the connected cells reach further than the smoothing distance
+            if dist >= config.smoothing_distance {
+                continue;
+            }
+            let dir = (sample_point - pos) / dist;
+            let cohesion = rest_volume * cohesion_spline(dist, config.smoothing_distance) * dir;
+            let curvature =
+                neighbour_share * (normals[sample_particle_index] - normals[particle_index]);
+            // particles with too few neighbours get pulled in harder
+            let correction = (config.rest_density(fluid_kinds[sample_particle_index])
+                + config.rest_density(fluid_kinds[particle_index]))
+                / (densities[sample_particle_index] + densities[particle_index]);
+            acceleration -= correction * (cohesion + curvature);
+        }
+    }
+    acceleration * config.surface_tension
+}
+
+// attracts from half the smoothing distance on and repels closer than that,
+// normalized to integrate to 1 in 2D like the kernels
+fn cohesion_spline(distance: f32, h: f32) -> f32 {
+    if distance >= h {
+        return 0f32;
+    }
+    let normalization = 35840f32 / (209f32 * PI * h.powi(8));
+    let shape = (h - distance).powi(3) * distance.powi(3);
+    if distance > h / 2f32 {
+        normalization * shape
+    } else {
+        normalization * (2f32 * shape - h.powi(6) / 64f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec2;
+
+    use super::*;
+    use crate::{particles_spawning, scenario::Scenario};
+
+    // every particle takes up the same room whatever its fluid, so a honey block feels the
+    // same surface tension as a water block of the same shape
+    #[test]
+    fn surface_tension_is_the_same_for_every_fluid() {
+        let config = SimConfig {
+            surface_tension: 1000f32,
+            box_size: vec2(200f32, 100f32),
+            ..SimConfig::default()
+        };
+        let scenario: Scenario = toml::from_str(
+            r#"
+[[fluid]]
+shape = { type = "rect", center = [-50.0, 0.0], size = [30.0, 30.0] }
+
+[[fluid]]
+shape = { type = "rect", center = [50.0, 0.0], size = [30.0, 30.0] }
+fluid = "honey"
+"#,
+        )
+        .unwrap();
+        let mut world = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        world.update_neighbourhoods(world.positions.clone(), &config);
+        let block_size = world.len() / 2;
+        assert_eq!(world.fluid_kinds[block_size], FluidKind::Honey);
+        let accelerations: Vec<(Vec2, Vec2)> = (0..block_size)
+            .map(|i| {
+                (
+                    calculate_surface_tension(&world, i, &config),
+                    calculate_surface_tension(&world, block_size + i, &config),
+                )
+            })
+            .collect();
+        // inside the blocks the pulls cancel out down to rounding noise
+        let tolerance = accelerations
+            .iter()
+            .map(|(water, _)| water.length())
+            .fold(0f32, f32::max)
+            * 1e-4;
+        for (water, honey) in accelerations {
+            assert!(water.distance(honey) <= tolerance, "{} {}", water, honey);
+        }
+    }
+}
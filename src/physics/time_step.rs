@@ -0,0 +1,50 @@
+use crate::{fluid_world::FluidWorld, pressure_handler::INFLUENCE_MODIFIER, sim_config::SimConfig};
+
+// usual SPH safety factor for the force criterion, the CFL one comes from the config
+const FORCE_NUMBER: f32 = 0.25f32;
+// largest share of the velocity difference to its neighbours a particle may take per step.
+// a whole one would swap the velocities of two neighbours, more flips them back and forth
+const VISCOSITY_NUMBER: f32 = 0.5f32;
+
+// largest time step that keeps the current state stable:
+// no particle moves more than a fraction of the smoothing distance (CFL),
+// accelerations can't push it further than that either (force)
+// and viscosity doesn't overshoot the velocities of the neighbours
+pub fn stable_time_step(world: &FluidWorld, config: &SimConfig) -> f32 {
+    let h = config.smoothing_distance;
+    let max_speed = world
+        .velocities
+        .iter()
+        .map(|velocity| velocity.length())
+        .fold(0f32, f32::max);
+
+    let mut delta = f32::INFINITY;
+    if max_speed > 0f32 {
+        delta = delta.min(config.cfl_number * h / max_speed);
+    }
+    if world.max_acceleration > 0f32 {
+        delta = delta.min(FORCE_NUMBER * (h / world.max_acceleration).sqrt());
+    }
+    // a viscosity step moves a particle by `delta * viscosity * sum(W)` of the difference,
+    // the kernels are normalized like the density one, so the sum is its density per
+    // unit of influence
+    let max_viscous_rate = world
+        .fluid_kinds
+        .iter()
+        .zip(&world.densities)
+        .map(|(fluid, density)| {
+            let kernel_sum =
+                density / (INFLUENCE_MODIFIER * config.fluids.get(*fluid).relative_density);
+            config.viscosity(*fluid) * kernel_sum
+        })
+        .fold(0f32, f32::max);
+    if max_viscous_rate > 0f32 {
+        delta = delta.min(VISCOSITY_NUMBER / max_viscous_rate);
+    }
+    delta
+}
+
+// time step for the next adaptive step, never above the fixed `physics_step`
+pub fn adaptive_time_step(world: &FluidWorld, config: &SimConfig) -> f32 {
+    stable_time_step(world, config).clamp(config.min_physics_step, config.physics_step)
+}
@@ -1,33 +1,31 @@
 use bevy::prelude::*;
-use ops::FloatPow;
 
-use crate::{particle_grid::TOTAL_GRID_SIZE, pressure_handler::SMOOTHING_DISTANCE};
-fn viscosity_smoothing(distance: f32) -> f32 {
-    let value: f32 = 0f32.max((SMOOTHING_DISTANCE as f32).squared() - distance.squared());
-    value * value * value
-}
-const VISCOSITY_STRENGTH: f32 = 0.000000001f32;
-pub fn calculate_viscosity_force(
-    sample_point: Vec2,
-    sample_velocity: Vec2,
-    particles_pos: &[Vec2],
-    connected_cells: &[usize],
-    particles_gird: &[Vec<usize>],
-    velocities: &[Vec2],
-) -> Vec2 {
+use crate::{fluid_world::FluidWorld, sim_config::SimConfig};
+pub fn calculate_viscosity_force(world: &FluidWorld, index: usize, config: &SimConfig) -> Vec2 {
+    let sample_point = world.predicted_positions[index];
+    let sample_velocity = world.velocities[index];
+    let (particles_pos, particles_gird) = (&world.predicted_positions, &world.grid);
+    let (velocities, fluid_kinds) = (&world.velocities, &world.fluid_kinds);
+    let sample_viscosity = config.viscosity(fluid_kinds[index]);
+    let kernel = config.kernels.viscosity.kernel();
     let mut viscosity_force = Vec2::ZERO;
-    for cell in connected_cells {
-        if cell == &usize::MAX || cell > &TOTAL_GRID_SIZE {
+    for cell in world.particle_connected_cells(index) {
+        if cell == &usize::MAX || cell >= &particles_gird.len() {
             continue;
         }
         for index_ref in &particles_gird[cell.to_owned()] {
             let particle_index = index_ref.to_owned();
             let distance = particles_pos[particle_index].distance(sample_point);
-            let influence = viscosity_smoothing(distance);
+            let influence = kernel.value(distance, config.smoothing_distance);
+
+            // two fluids rub against each other with their average viscosity
+            let viscosity =
+                (sample_viscosity + config.viscosity(fluid_kinds[particle_index])) / 2f32;
 
-            viscosity_force += (velocities[particle_index] - sample_velocity) * influence;
+            viscosity_force +=
+                (velocities[particle_index] - sample_velocity) * influence * viscosity;
         }
     }
     // println!("viscosity_force {}", viscosity_force);
-    viscosity_force * VISCOSITY_STRENGTH
+    viscosity_force
 }
@@ -0,0 +1,402 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::math::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collisions::{points_bounds, polygon_contains},
+    fluid_kind::FluidKind,
+    obstacles::Obstacle,
+};
+
+// in square pixels, thinner polygons hold no particles and have no mass or inertia to speak of
+const MIN_POLYGON_AREA: f32 = 1f32;
+
+// initial setup of a run, shared as a file so everyone starts from the same state
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub fluid: Vec<FluidRegion>,
+    pub wall: Vec<Wall>,
+    pub emitter: Vec<Emitter>,
+    pub body: Vec<Body>,
+    pub obstacle: Vec<Obstacle>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FluidRegion {
+    pub shape: Shape,
+    #[serde(default)]
+    pub velocity: Vec2,
+    #[serde(default)]
+    pub fluid: FluidKind,
+    // overrides the mass that comes with the fluid
+    pub mass: Option<f32>,
+    // starting temperature, [physics.heat] reference_temperature if missing
+    pub temperature: Option<f32>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Shape {
+    Rect { center: Vec2, size: Vec2 },
+    Circle { center: Vec2, radius: f32 },
+    Polygon { points: Vec<Vec2> },
+}
+
+// straight solid segment, particles bounce off it like off the container
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wall {
+    pub start: Vec2,
+    pub end: Vec2,
+    #[serde(default = "default_wall_thickness")]
+    pub thickness: f32,
+}
+
+// keeps adding particles along a line while the simulation runs
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Emitter {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    // particles per second of simulation time
+    pub rate: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub fluid: FluidKind,
+    pub mass: Option<f32>,
+    pub temperature: Option<f32>,
+    pub max_particles: Option<u32>,
+}
+
+// rigid body floating in the fluid, the shape gives its starting position
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Body {
+    pub shape: Shape,
+    // degrees around the center of mass, counter clockwise
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub velocity: Vec2,
+    // degrees per second
+    #[serde(default)]
+    pub angular_velocity: f32,
+    // mass compared to water filling the same area, below 1 floats
+    #[serde(default = "default_body_density")]
+    pub relative_density: f32,
+    // overrides the mass from the density
+    pub mass: Option<f32>,
+}
+
+fn default_wall_thickness() -> f32 {
+    4f32
+}
+fn default_body_density() -> f32 {
+    0.5f32
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(|err| ScenarioError::Io {
+            path: path.to_owned(),
+            err,
+        })?;
+        let scenario: Scenario = toml::from_str(&text).map_err(|err| ScenarioError::Parse {
+            path: path.to_owned(),
+            message: err.to_string(),
+        })?;
+
+        let invalid = scenario.find_invalid_values();
+        if !invalid.is_empty() {
+            return Err(ScenarioError::Invalid {
+                path: path.to_owned(),
+                invalid,
+            });
+        }
+        Ok(scenario)
+    }
+
+    fn find_invalid_values(&self) -> Vec<String> {
+        let mut invalid = Vec::new();
+        for (i, region) in self.fluid.iter().enumerate() {
+            if region
+                .mass
+                .is_some_and(|mass| !mass.is_finite() || mass <= 0f32)
+            {
+                invalid.push(format!(
+                    "fluid[{}].mass: must be finite and greater than 0",
+                    i
+                ));
+            }
+            if !region.velocity.is_finite() {
+                invalid.push(format!("fluid[{}].velocity: must be finite", i));
+            }
+            if region
+                .temperature
+                .is_some_and(|temperature| !temperature.is_finite())
+            {
+                invalid.push(format!("fluid[{}].temperature: must be finite", i));
+            }
+            region
+                .shape
+                .find_invalid_values(&format!("fluid[{}].shape", i), &mut invalid);
+        }
+        for (i, wall) in self.wall.iter().enumerate() {
+            if !wall.start.is_finite() || !wall.end.is_finite() {
+                invalid.push(format!("wall[{}]: start and end must be finite", i));
+            }
+            if !wall.thickness.is_finite() || wall.thickness < 0f32 {
+                invalid.push(format!(
+                    "wall[{}].thickness: must be finite and not negative",
+                    i
+                ));
+            }
+        }
+        for (i, body) in self.body.iter().enumerate() {
+            body.shape
+                .find_invalid_values(&format!("body[{}].shape", i), &mut invalid);
+            if let Shape::Polygon { points } = &body.shape
+                && encloses_area(points)
+                && !is_convex(points)
+            {
+                invalid.push(format!("body[{}].shape.points: must be convex", i));
+            }
+            if !body.angle.is_finite() || !body.angular_velocity.is_finite() {
+                invalid.push(format!(
+                    "body[{}]: angle and angular_velocity must be finite",
+                    i
+                ));
+            }
+            if !body.velocity.is_finite() {
+                invalid.push(format!("body[{}].velocity: must be finite", i));
+            }
+            if !body.relative_density.is_finite() || body.relative_density <= 0f32 {
+                invalid.push(format!(
+                    "body[{}].relative_density: must be finite and greater than 0",
+                    i
+                ));
+            }
+            if body
+                .mass
+                .is_some_and(|mass| !mass.is_finite() || mass <= 0f32)
+            {
+                invalid.push(format!(
+                    "body[{}].mass: must be finite and greater than 0",
+                    i
+                ));
+            }
+        }
+        for (i, obstacle) in self.obstacle.iter().enumerate() {
+            obstacle
+                .shape
+                .find_invalid_values(&format!("obstacle[{}].shape", i), &mut invalid);
+            if !(0f32..=1f32).contains(&obstacle.restitution) {
+                invalid.push(format!(
+                    "obstacle[{}].restitution: must be between 0 and 1",
+                    i
+                ));
+            }
+            if !(0f32..=1f32).contains(&obstacle.friction) {
+                invalid.push(format!("obstacle[{}].friction: must be between 0 and 1", i));
+            }
+        }
+        for (i, emitter) in self.emitter.iter().enumerate() {
+            if !emitter.position.is_finite() || !emitter.velocity.is_finite() {
+                invalid.push(format!(
+                    "emitter[{}]: position and velocity must be finite",
+                    i
+                ));
+            }
+            // an endless rate would keep emitting forever within a single step
+            if !emitter.rate.is_finite() || emitter.rate < 0f32 {
+                invalid.push(format!(
+                    "emitter[{}].rate: must be finite and not negative",
+                    i
+                ));
+            }
+            if !emitter.width.is_finite() || emitter.width < 0f32 {
+                invalid.push(format!(
+                    "emitter[{}].width: must be finite and not negative",
+                    i
+                ));
+            }
+            if emitter
+                .mass
+                .is_some_and(|mass| !mass.is_finite() || mass <= 0f32)
+            {
+                invalid.push(format!(
+                    "emitter[{}].mass: must be finite and greater than 0",
+                    i
+                ));
+            }
+            if emitter
+                .temperature
+                .is_some_and(|temperature| !temperature.is_finite())
+            {
+                invalid.push(format!("emitter[{}].temperature: must be finite", i));
+            }
+        }
+        invalid
+    }
+}
+
+impl FluidRegion {
+    // particles on a regular grid with `spacing` that fall inside the shape. regions always
+    // spawn at the rest spacing, any other would start the fluid compressed or stretched.
+    // only the part inside the container of `box_size` is sampled, the grid stays lined up
+    // with the shape
+    pub fn particle_positions(&self, spacing: f32, box_size: Vec2) -> Vec<Vec2> {
+        let (min, max) = self.shape.bounds();
+        let half_size = box_size / 2f32;
+        let start = min + ((-half_size - min) / spacing).ceil().max(Vec2::ZERO) * spacing;
+        let end = max.min(half_size);
+        let mut output = Vec::new();
+        let mut y = start.y;
+        while y <= end.y {
+            let mut x = start.x;
+            while x <= end.x {
+                let pos = vec2(x, y);
+                if self.shape.contains(pos) {
+                    output.push(pos);
+                }
+                x += spacing;
+            }
+            y += spacing;
+        }
+        output
+    }
+}
+
+impl Shape {
+    // `name` is where the shape is in the file, like `fluid[0].shape`
+    fn find_invalid_values(&self, name: &str, invalid: &mut Vec<String>) {
+        let finite = match self {
+            Shape::Rect { center, size } => center.is_finite() && size.is_finite(),
+            Shape::Circle { center, radius } => center.is_finite() && radius.is_finite(),
+            Shape::Polygon { points } => points.iter().all(|point| point.is_finite()),
+        };
+        if !finite {
+            invalid.push(format!("{}: every value must be finite", name));
+            return;
+        }
+        match self {
+            Shape::Rect { size, .. } if size.cmple(Vec2::ZERO).any() => {
+                invalid.push(format!("{}.size: both sides must be greater than 0", name))
+            }
+            Shape::Circle { radius, .. } if *radius <= 0f32 => {
+                invalid.push(format!("{}.radius: must be greater than 0", name))
+            }
+            Shape::Polygon { points } if points.len() < 3 => {
+                invalid.push(format!("{}.points: needs at least 3 points", name))
+            }
+            Shape::Polygon { points } if !encloses_area(points) => {
+                invalid.push(format!("{}.points: must enclose an area", name))
+            }
+            _ => {}
+        }
+    }
+
+    fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Shape::Rect { center, size } => (center - size / 2f32, center + size / 2f32),
+            Shape::Circle { center, radius } => {
+                (center - Vec2::splat(*radius), center + Vec2::splat(*radius))
+            }
+            Shape::Polygon { points } => points_bounds(points),
+        }
+    }
+
+    fn contains(&self, pos: Vec2) -> bool {
+        match self {
+            Shape::Rect { center, size } => {
+                let offset = (pos - center).abs();
+                offset.x <= size.x / 2f32 && offset.y <= size.y / 2f32
+            }
+            Shape::Circle { center, radius } => pos.distance_squared(*center) <= radius * radius,
+            Shape::Polygon { points } => polygon_contains(points, pos),
+        }
+    }
+}
+
+// signed, positive when the points go counter clockwise
+fn polygon_area(points: &[Vec2]) -> f32 {
+    (0..points.len())
+        .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+        .sum::<f32>()
+        / 2f32
+}
+
+pub fn encloses_area(points: &[Vec2]) -> bool {
+    polygon_area(points).abs() > MIN_POLYGON_AREA
+}
+
+// every corner turns the same way, points on a straight line are fine
+fn is_convex(points: &[Vec2]) -> bool {
+    let turns: Vec<f32> = (0..points.len())
+        .map(|i| {
+            let (a, b, c) = (
+                points[i],
+                points[(i + 1) % points.len()],
+                points[(i + 2) % points.len()],
+            );
+            (b - a).perp_dot(c - b)
+        })
+        .collect();
+    turns.iter().all(|turn| *turn >= 0f32) || turns.iter().all(|turn| *turn <= 0f32)
+}
+
+impl Emitter {
+    // slot positions `spacing` apart along the emitter line, perpendicular to the
+    // emitting direction
+    pub fn slot_position(&self, slot: u32, spacing: f32) -> Vec2 {
+        let slots = self.slots_count(spacing);
+        let side = self.velocity.normalize_or(Vec2::Y).perp();
+        let offset = slot as f32 - (slots - 1) as f32 / 2f32;
+        self.position + side * offset * spacing
+    }
+
+    pub fn slots_count(&self, spacing: f32) -> u32 {
+        ((self.width / spacing) as u32).max(1)
+    }
+}
+
+pub enum ScenarioError {
+    Io { path: PathBuf, err: io::Error },
+    Parse { path: PathBuf, message: String },
+    Invalid { path: PathBuf, invalid: Vec<String> },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io { path, err } => {
+                write!(f, "can't read scenario {}: {}", path.display(), err)
+            }
+            ScenarioError::Parse { path, message } => {
+                write!(f, "can't parse scenario {}: {}", path.display(), message)
+            }
+            ScenarioError::Invalid { path, invalid } => {
+                write!(f, "invalid values in scenario {}:", path.display())?;
+                for value in invalid {
+                    write!(f, "\n  {}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ScenarioError {}
@@ -0,0 +1,592 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::{ecs::system::SystemParam, math::*, prelude::*};
+use serde::{
+    Deserialize, Serialize,
+    de::{self, IntoDeserializer, value::StrDeserializer},
+};
+
+use crate::{
+    bounding_box::BOX_BOUNDS_SIZE_PIXELS,
+    collisions,
+    export::ExportConfig,
+    fluid_kind::FluidKind,
+    frame_recording::RecordingConfig,
+    particle_physics::Particle,
+    particles_spawning::{self, SpawnConfig},
+    scenario::{Scenario, ScenarioError},
+    sim_config::SimConfig,
+};
+
+const RELOAD_CHECK_INTERVAL_SECS: f32 = 0.5f32;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContainerConfig {
+    pub size: Vec2,
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        ContainerConfig {
+            size: BOX_BOUNDS_SIZE_PIXELS,
+        }
+    }
+}
+
+// layout of the settings file, every section and key is optional
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub physics: SimConfig,
+    pub spawning: SpawnConfig,
+    pub container: ContainerConfig,
+    pub export: ExportConfig,
+    pub recording: RecordingConfig,
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Settings, SettingsError> {
+        let text = fs::read_to_string(path).map_err(|err| SettingsError::Io {
+            path: path.to_owned(),
+            err,
+        })?;
+        let mut settings = Settings::parse(&text).map_err(|err| err.with_path(path))?;
+
+        if let Some(scenario_path) = &settings.spawning.scenario_path {
+            let scenario_path = scenario_file(path, scenario_path);
+            let scenario = Scenario::load(&scenario_path).map_err(SettingsError::Scenario)?;
+            settings.spawning.scenario = Some(scenario);
+        }
+        Ok(settings)
+    }
+
+    pub fn parse(text: &str) -> Result<Settings, SettingsError> {
+        let settings: Settings = toml::from_str(text).map_err(|err| SettingsError::Parse {
+            path: None,
+            message: err.to_string(),
+        })?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn sim_config(&self) -> SimConfig {
+        SimConfig {
+            box_size: self.container.size,
+            particle_spacing: self.spawning.particles_spacing,
+            ..self.physics.clone()
+        }
+        .with_rest_density()
+    }
+
+    fn validate(&self) -> Result<(), SettingsError> {
+        let physics = &self.physics;
+        let spawning = &self.spawning;
+        let mut invalid = Vec::new();
+        let mut check = |ok: bool, key: &str, reason: &'static str| {
+            if !ok {
+                invalid.push(InvalidSetting {
+                    key: key.to_string(),
+                    reason,
+                });
+            }
+        };
+
+        check(
+            physics.gravity.is_finite(),
+            "physics.gravity",
+            "must be finite",
+        );
+        check(
+            physics.time_scale.is_finite() && physics.time_scale >= 0f32,
+            "physics.time_scale",
+            "must be finite and not negative",
+        );
+        check(
+            physics.physics_step.is_finite() && physics.physics_step > 0f32,
+            "physics.physics_step",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.max_steps_per_frame > 0,
+            "physics.max_steps_per_frame",
+            "must be at least 1",
+        );
+        check(
+            physics.pressure_solve.max_density_error.is_finite()
+                && physics.pressure_solve.max_density_error > 0f32,
+            "physics.pressure_solve.max_density_error",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.pressure_solve.max_divergence_error.is_finite()
+                && physics.pressure_solve.max_divergence_error > 0f32,
+            "physics.pressure_solve.max_divergence_error",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.pressure_solve.max_iterations > 0
+                && physics.pressure_solve.min_iterations <= physics.pressure_solve.max_iterations,
+            "physics.pressure_solve.max_iterations",
+            "must be at least 1 and not below min_iterations",
+        );
+        check(
+            physics.pbf.iterations > 0,
+            "physics.pbf.iterations",
+            "must be at least 1",
+        );
+        check(
+            physics.pbf.constraint_relaxation.is_finite()
+                && physics.pbf.constraint_relaxation > 0f32,
+            "physics.pbf.constraint_relaxation",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.pbf.tensile_strength.is_finite() && physics.pbf.tensile_strength >= 0f32,
+            "physics.pbf.tensile_strength",
+            "must be finite and not negative",
+        );
+        // a negative power blows up for far neighbours whose kernel value goes to 0
+        check(
+            physics.pbf.tensile_exponent >= 1,
+            "physics.pbf.tensile_exponent",
+            "must be at least 1",
+        );
+        check(
+            physics.pbf.tensile_distance > 0f32 && physics.pbf.tensile_distance < 1f32,
+            "physics.pbf.tensile_distance",
+            "must be between 0 and 1",
+        );
+        check(
+            (0f32..=1f32).contains(&physics.pbf.xsph_viscosity),
+            "physics.pbf.xsph_viscosity",
+            "must be between 0 and 1",
+        );
+        check(
+            physics.flip.cell_size.is_finite() && physics.flip.cell_size > 0f32,
+            "physics.flip.cell_size",
+            "must be finite and greater than 0",
+        );
+        check(
+            (0f32..=1f32).contains(&physics.flip.flip_ratio),
+            "physics.flip.flip_ratio",
+            "must be between 0 and 1",
+        );
+        check(
+            physics.flip.over_relaxation > 0f32 && physics.flip.over_relaxation < 2f32,
+            "physics.flip.over_relaxation",
+            "must be between 0 and 2",
+        );
+        check(
+            physics.flip.drift_correction.is_finite() && physics.flip.drift_correction >= 0f32,
+            "physics.flip.drift_correction",
+            "must be finite and not negative",
+        );
+        check(
+            physics.cfl_number.is_finite() && physics.cfl_number > 0f32,
+            "physics.cfl_number",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.min_physics_step > 0f32 && physics.min_physics_step <= physics.physics_step,
+            "physics.min_physics_step",
+            "must be greater than 0 and not above physics_step",
+        );
+        check(
+            physics.pressure_multiplier.is_finite() && physics.pressure_multiplier >= 0f32,
+            "physics.pressure_multiplier",
+            "must be finite and not negative",
+        );
+        check(
+            physics.near_pressure_multiplier.is_finite()
+                && physics.near_pressure_multiplier >= 0f32,
+            "physics.near_pressure_multiplier",
+            "must be finite and not negative",
+        );
+        check(
+            physics.pressure_force_modifier.is_finite(),
+            "physics.pressure_force_modifier",
+            "must be finite",
+        );
+        check(
+            physics.viscosity_strength.is_finite() && physics.viscosity_strength >= 0f32,
+            "physics.viscosity_strength",
+            "must be finite and not negative",
+        );
+        for fluid in FluidKind::ALL {
+            let properties = physics.fluids.get(fluid);
+            check(
+                properties.relative_density.is_finite() && properties.relative_density > 0f32,
+                &format!("physics.fluids.{}.relative_density", fluid.name()),
+                "must be finite and greater than 0",
+            );
+            check(
+                properties.relative_viscosity.is_finite() && properties.relative_viscosity >= 0f32,
+                &format!("physics.fluids.{}.relative_viscosity", fluid.name()),
+                "must be finite and not negative",
+            );
+            check(
+                properties.color.cmpge(Vec3::ZERO).all() && properties.color.cmple(Vec3::ONE).all(),
+                &format!("physics.fluids.{}.color", fluid.name()),
+                "every channel must be between 0 and 1",
+            );
+        }
+        check(
+            physics.surface_tension.is_finite() && physics.surface_tension >= 0f32,
+            "physics.surface_tension",
+            "must be finite and not negative",
+        );
+        check(
+            physics.heat.reference_temperature.is_finite(),
+            "physics.heat.reference_temperature",
+            "must be finite",
+        );
+        check(
+            physics.heat.diffusivity.is_finite() && physics.heat.diffusivity >= 0f32,
+            "physics.heat.diffusivity",
+            "must be finite and not negative",
+        );
+        check(
+            physics.heat.thermal_expansion.is_finite(),
+            "physics.heat.thermal_expansion",
+            "must be finite",
+        );
+        let walls = &physics.heat.walls;
+        for (side, temperature) in [
+            ("left", walls.left),
+            ("right", walls.right),
+            ("bottom", walls.bottom),
+            ("top", walls.top),
+        ] {
+            check(
+                temperature.is_none_or(f32::is_finite),
+                &format!("physics.heat.walls.{}", side),
+                "must be finite",
+            );
+        }
+        check(
+            physics.heat.wall_heat_transfer.is_finite() && physics.heat.wall_heat_transfer >= 0f32,
+            "physics.heat.wall_heat_transfer",
+            "must be finite and not negative",
+        );
+        // a particle can't take more than the whole difference in one step
+        check(
+            physics.heat.wall_heat_transfer * physics.physics_step <= 1f32,
+            "physics.heat.wall_heat_transfer",
+            "must not be above 1 / physics_step",
+        );
+        check(
+            physics
+                .heat
+                .color_range
+                .is_none_or(|range| range.is_finite() && range.x < range.y),
+            "physics.heat.color_range",
+            "must be finite, the first temperature below the second",
+        );
+        check(
+            physics.heat.freezing_temperature.is_none_or(f32::is_finite),
+            "physics.heat.freezing_temperature",
+            "must be finite",
+        );
+        check(
+            physics.heat.latent_heat.is_finite() && physics.heat.latent_heat >= 0f32,
+            "physics.heat.latent_heat",
+            "must be finite and not negative",
+        );
+        check(
+            physics.heat.freezing_expansion.is_finite(),
+            "physics.heat.freezing_expansion",
+            "must be finite",
+        );
+        check(
+            (0f32..=1f32).contains(&physics.collision_damping),
+            "physics.collision_damping",
+            "must be between 0 and 1",
+        );
+        check(
+            physics.smoothing_distance.is_finite() && physics.smoothing_distance > 0f32,
+            "physics.smoothing_distance",
+            "must be finite and greater than 0",
+        );
+        check(
+            physics.air_density.is_finite() && physics.air_density >= 0f32,
+            "physics.air_density",
+            "must be finite and not negative",
+        );
+        check(
+            physics.particle_drag_coefficient.is_finite()
+                && physics.particle_drag_coefficient >= 0f32,
+            "physics.particle_drag_coefficient",
+            "must be finite and not negative",
+        );
+        check(
+            physics.fixed_delta.is_finite() && physics.fixed_delta > 0f32,
+            "physics.fixed_delta",
+            "must be finite and greater than 0",
+        );
+
+        check(
+            spawning.particles_count > 0,
+            "spawning.particles_count",
+            "must be at least 1",
+        );
+        check(
+            spawning.particles_layers > 0,
+            "spawning.particles_layers",
+            "must be at least 1",
+        );
+        check(
+            spawning.particles_spacing.is_finite() && spawning.particles_spacing > 0f32,
+            "spawning.particles_spacing",
+            "must be finite and greater than 0",
+        );
+
+        // particles are kept a radius away from every side
+        check(
+            self.container.size.is_finite()
+                && self
+                    .container
+                    .size
+                    .cmpgt(Vec2::splat(2f32 * collisions::particle_radius()))
+                    .all(),
+            "container.size",
+            "both sides must be finite and wider than a particle",
+        );
+        // the grid needs at least 2 cells across for the faces around a point to fit in it
+        check(
+            physics.flip.cell_size * 2f32 <= self.container.size.min_element(),
+            "physics.flip.cell_size",
+            "must not be above half the smaller container side",
+        );
+
+        check(
+            self.export.interval > 0,
+            "export.interval",
+            "must be at least 1",
+        );
+
+        check(
+            self.recording.interval > 0,
+            "recording.interval",
+            "must be at least 1",
+        );
+        check(
+            self.recording.width > 0,
+            "recording.width",
+            "must be greater than 0",
+        );
+        check(
+            self.recording.height > 0,
+            "recording.height",
+            "must be greater than 0",
+        );
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid {
+                path: None,
+                invalid,
+            })
+        }
+    }
+}
+
+pub struct InvalidSetting {
+    pub key: String,
+    pub reason: &'static str,
+}
+
+pub enum SettingsError {
+    Io {
+        path: PathBuf,
+        err: io::Error,
+    },
+    Parse {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    Invalid {
+        path: Option<PathBuf>,
+        invalid: Vec<InvalidSetting>,
+    },
+    Scenario(ScenarioError),
+}
+
+impl SettingsError {
+    fn with_path(self, file_path: &Path) -> SettingsError {
+        match self {
+            SettingsError::Parse { message, .. } => SettingsError::Parse {
+                path: Some(file_path.to_owned()),
+                message,
+            },
+            SettingsError::Invalid { invalid, .. } => SettingsError::Invalid {
+                path: Some(file_path.to_owned()),
+                invalid,
+            },
+            io => io,
+        }
+    }
+}
+
+// value of a single name like `"csv"` the way the settings file spells it, so command line
+// options don't keep their own list of names
+pub fn parse_name<'a, T: Deserialize<'a>>(name: &'a str) -> Option<T> {
+    let deserializer: StrDeserializer<'a, de::value::Error> = name.into_deserializer();
+    T::deserialize(deserializer).ok()
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => format!(" {}", path.display()),
+        None => String::new(),
+    }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io { path, err } => {
+                write!(f, "can't read settings file {}: {}", path.display(), err)
+            }
+            SettingsError::Parse { path, message } => {
+                write!(
+                    f,
+                    "can't parse settings file{}: {}",
+                    display_path(path),
+                    message
+                )
+            }
+            SettingsError::Invalid { path, invalid } => {
+                write!(f, "invalid values in settings file{}:", display_path(path))?;
+                for setting in invalid {
+                    write!(f, "\n  {}: {}", setting.key, setting.reason)?;
+                }
+                Ok(())
+            }
+            SettingsError::Scenario(err) => err.fmt(f),
+        }
+    }
+}
+
+impl fmt::Debug for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+// scenario paths in the settings are relative to the settings file
+fn scenario_file(settings_path: &Path, scenario_path: &Path) -> PathBuf {
+    match settings_path.parent() {
+        Some(settings_dir) => settings_dir.join(scenario_path),
+        None => scenario_path.to_owned(),
+    }
+}
+
+// settings file given on the command line and the scenario it points at,
+// checked every so often for changes
+#[derive(Resource)]
+pub struct SettingsFileWatcher {
+    path: PathBuf,
+    last_modified: [Option<SystemTime>; 2],
+    timer: Timer,
+}
+
+impl SettingsFileWatcher {
+    pub fn new(path: PathBuf, spawning: &SpawnConfig) -> SettingsFileWatcher {
+        SettingsFileWatcher {
+            last_modified: modified_times(&path, spawning),
+            path,
+            timer: Timer::from_seconds(RELOAD_CHECK_INTERVAL_SECS, TimerMode::Repeating),
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// of the settings file and of its scenario
+fn modified_times(path: &Path, spawning: &SpawnConfig) -> [Option<SystemTime>; 2] {
+    let scenario_modified = spawning
+        .scenario_path
+        .as_ref()
+        .and_then(|scenario_path| modified_time(&scenario_file(path, scenario_path)));
+    [modified_time(path), scenario_modified]
+}
+
+// every resource that comes from the settings file
+#[derive(SystemParam)]
+pub struct SettingsResources<'w> {
+    config: ResMut<'w, SimConfig>,
+    spawn_config: ResMut<'w, SpawnConfig>,
+    export_config: ResMut<'w, ExportConfig>,
+    recording_config: ResMut<'w, RecordingConfig>,
+}
+
+pub fn hot_reload_settings(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    watcher: Option<ResMut<SettingsFileWatcher>>,
+    time: Res<Time>,
+    resources: SettingsResources,
+    particles: Query<Entity, With<Particle>>,
+) {
+    let Some(mut watcher) = watcher else {
+        return;
+    };
+    if !watcher.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let modified = modified_times(&watcher.path, &resources.spawn_config);
+    if modified == watcher.last_modified {
+        return;
+    }
+    watcher.last_modified = modified;
+
+    let settings = match Settings::load(&watcher.path) {
+        Ok(settings) => settings,
+        Err(err) => {
+            // keep running with the last good settings
+            error!("{}", err);
+            return;
+        }
+    };
+    info!("reloaded settings from {}", watcher.path.display());
+
+    let SettingsResources {
+        mut config,
+        mut spawn_config,
+        mut export_config,
+        mut recording_config,
+    } = resources;
+    let new_config = settings.sim_config();
+    let needs_respawn =
+        settings.spawning != *spawn_config || new_config.box_size != config.box_size;
+    if new_config != *config {
+        *config = new_config;
+    }
+    if settings.spawning != *spawn_config {
+        *spawn_config = settings.spawning;
+    }
+    if settings.export != *export_config {
+        *export_config = settings.export;
+    }
+    if settings.recording != *recording_config {
+        *recording_config = settings.recording;
+    }
+    if needs_respawn {
+        particles_spawning::respawn_particles(
+            &mut commands,
+            &asset_server,
+            &particles,
+            particles_spawning::create_fluid_world(&spawn_config, &config),
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use bevy::{math::*, prelude::*};
+
+use crate::{
+    fluid_kind::FluidKind,
+    fluid_world::{self, FluidWorld},
+    particle_physics::Particle,
+    particles_spawning::{self, PARTICLE_RAY},
+    phase_change::IceCluster,
+};
+
+// file layout (little endian):
+// header: magic "FSNP", version u16, reserved u16, particles count u32, steps u64, time f32,
+// step the accelerations were evaluated at the end of u64 (u64::MAX when they weren't)
+// then for every particle: position, velocity, last velocity, acceleration (2 x f32 each),
+// mass f32, density f32, near density f32, fluid kind u32, temperature f32,
+// liquid fraction f32, affine velocity (4 x f32 column major)
+// then emitters count u32 and for every emitter accumulated f32, emitted u32,
+// bodies count u32 and for every body position (2 x f32), angle f32, velocity (2 x f32),
+// angular velocity f32,
+// ice clusters count u32 and for every cluster particles count u32 and for every particle
+// its index u32 and rest offset (2 x f32)
+const MAGIC: [u8; 4] = *b"FSNP";
+const VERSION: u16 = 1;
+const FLOATS_PER_PARTICLE: usize = 11;
+const FLOATS_PER_BODY: usize = 6;
+const NO_END_ACCELERATIONS: u64 = u64::MAX;
+// the count comes from the file, a broken one shouldn't reserve gigabytes up front
+const MAX_PREALLOCATED_PARTICLES: usize = 1 << 20;
+
+const QUICK_SAVE_PATH: &str = "snapshot.fsnap";
+const SAVE_KEY: KeyCode = KeyCode::F5;
+const LOAD_KEY: KeyCode = KeyCode::F9;
+
+pub enum SnapshotError {
+    Io(io::Error),
+    NotASnapshot,
+    UnsupportedVersion(u16),
+    // the snapshot was saved from a scenario with other emitters or bodies
+    SetupMismatch {
+        what: &'static str,
+        saved: usize,
+        running: usize,
+    },
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot io error: {}", err),
+            SnapshotError::NotASnapshot => write!(f, "file is not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot version {} is not supported (expected {})",
+                version, VERSION
+            ),
+            SnapshotError::SetupMismatch {
+                what,
+                saved,
+                running,
+            } => write!(
+                f,
+                "snapshot has {} {} but the running scenario has {}",
+                saved, what, running
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+pub fn write_snapshot(world: &FluidWorld, writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&(world.len() as u32).to_le_bytes())?;
+    writer.write_all(&world.steps.to_le_bytes())?;
+    writer.write_all(&world.time.to_le_bytes())?;
+    let end_accelerations_step = world.end_accelerations_step.unwrap_or(NO_END_ACCELERATIONS);
+    writer.write_all(&end_accelerations_step.to_le_bytes())?;
+
+    for i in 0..world.len() {
+        let values: [f32; FLOATS_PER_PARTICLE] = [
+            world.positions[i].x,
+            world.positions[i].y,
+            world.velocities[i].x,
+            world.velocities[i].y,
+            world.last_velocities[i].x,
+            world.last_velocities[i].y,
+            world.accelerations[i].x,
+            world.accelerations[i].y,
+            world.masses[i],
+            world.densities[i],
+            world.near_densities[i],
+        ];
+        for value in values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&world.fluid_kinds[i].index().to_le_bytes())?;
+        writer.write_all(&world.temperatures[i].to_le_bytes())?;
+        writer.write_all(&world.liquid_fractions[i].to_le_bytes())?;
+        for value in world.affine_velocities[i].to_cols_array() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    writer.write_all(&(world.emitters.len() as u32).to_le_bytes())?;
+    for state in &world.emitters {
+        writer.write_all(&state.accumulated.to_le_bytes())?;
+        writer.write_all(&state.emitted.to_le_bytes())?;
+    }
+    writer.write_all(&(world.bodies.len() as u32).to_le_bytes())?;
+    for body in &world.bodies {
+        let values: [f32; FLOATS_PER_BODY] = [
+            body.position.x,
+            body.position.y,
+            body.angle,
+            body.velocity.x,
+            body.velocity.y,
+            body.angular_velocity,
+        ];
+        for value in values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.write_all(&(world.ice_clusters.len() as u32).to_le_bytes())?;
+    for cluster in &world.ice_clusters {
+        writer.write_all(&(cluster.particles.len() as u32).to_le_bytes())?;
+        for (index, rest_offset) in cluster.particles.iter().zip(&cluster.rest_offsets) {
+            writer.write_all(&(*index as u32).to_le_bytes())?;
+            writer.write_all(&rest_offset.x.to_le_bytes())?;
+            writer.write_all(&rest_offset.y.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// walls, obstacles and the emitter and body definitions aren't stored, they come from `setup`,
+// the world of the scenario the snapshot was saved from. the particles, the progress of the
+// emitters, the motion of the bodies, the ice clusters and the step counters come from the
+// file. everything else is rebuilt by the next step
+pub fn read_snapshot(
+    reader: &mut impl Read,
+    setup: &FluidWorld,
+) -> Result<FluidWorld, SnapshotError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let version = u16::from_le_bytes(read_bytes(reader)?);
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let _reserved = u16::from_le_bytes(read_bytes(reader)?);
+    let particles_count = u32::from_le_bytes(read_bytes(reader)?) as usize;
+
+    let mut world = FluidWorld::with_capacity(particles_count.min(MAX_PREALLOCATED_PARTICLES));
+    world.walls = setup.walls.clone();
+    world.obstacles = setup.obstacles.clone();
+    world.emitters = setup.emitters.clone();
+    world.bodies = setup.bodies.clone();
+    world.steps = u64::from_le_bytes(read_bytes(reader)?);
+    world.time = f32::from_le_bytes(read_bytes(reader)?);
+    let end_accelerations_step = u64::from_le_bytes(read_bytes(reader)?);
+    for _ in 0..particles_count {
+        let mut values = [0f32; FLOATS_PER_PARTICLE];
+        for value in &mut values {
+            *value = f32::from_le_bytes(read_bytes(reader)?);
+        }
+        let fluid = FluidKind::from_index(u32::from_le_bytes(read_bytes(reader)?))
+            .ok_or(SnapshotError::NotASnapshot)?;
+        let temperature = f32::from_le_bytes(read_bytes(reader)?);
+        let liquid_fraction = f32::from_le_bytes(read_bytes(reader)?);
+        let mut affine_velocity = [0f32; 4];
+        for value in &mut affine_velocity {
+            *value = f32::from_le_bytes(read_bytes(reader)?);
+        }
+        let index = world.add_particle(
+            vec2(values[0], values[1]),
+            vec2(values[2], values[3]),
+            values[8],
+            fluid_world::particle_area(PARTICLE_RAY),
+            fluid,
+            temperature,
+        );
+        world.last_velocities[index] = vec2(values[4], values[5]);
+        world.accelerations[index] = vec2(values[6], values[7]);
+        world.densities[index] = values[9];
+        world.near_densities[index] = values[10];
+        world.liquid_fractions[index] = liquid_fraction;
+        world.affine_velocities[index] = Mat2::from_cols_array(&affine_velocity);
+    }
+    // the adaptive time step of the next step picks up where the saved one left off
+    world.update_max_acceleration();
+    world.end_accelerations_step =
+        (end_accelerations_step != NO_END_ACCELERATIONS).then_some(end_accelerations_step);
+
+    check_setup_count(reader, "emitters", world.emitters.len())?;
+    for state in &mut world.emitters {
+        state.accumulated = f32::from_le_bytes(read_bytes(reader)?);
+        state.emitted = u32::from_le_bytes(read_bytes(reader)?);
+    }
+    check_setup_count(reader, "bodies", world.bodies.len())?;
+    for body in &mut world.bodies {
+        let mut values = [0f32; FLOATS_PER_BODY];
+        for value in &mut values {
+            *value = f32::from_le_bytes(read_bytes(reader)?);
+        }
+        body.position = vec2(values[0], values[1]);
+        body.angle = values[2];
+        body.velocity = vec2(values[3], values[4]);
+        body.angular_velocity = values[5];
+    }
+
+    let clusters_count = u32::from_le_bytes(read_bytes(reader)?) as usize;
+    for _ in 0..clusters_count {
+        let cluster_size = u32::from_le_bytes(read_bytes(reader)?) as usize;
+        let mut cluster = IceCluster::default();
+        for _ in 0..cluster_size {
+            let index = u32::from_le_bytes(read_bytes(reader)?) as usize;
+            if index >= particles_count {
+                return Err(SnapshotError::NotASnapshot);
+            }
+            let x = f32::from_le_bytes(read_bytes(reader)?);
+            let y = f32::from_le_bytes(read_bytes(reader)?);
+            cluster.particles.push(index);
+            cluster.rest_offsets.push(vec2(x, y));
+        }
+        world.ice_clusters.push(cluster);
+    }
+    Ok(world)
+}
+
+// count of emitters or bodies in the file, it has to match the running scenario
+fn check_setup_count(
+    reader: &mut impl Read,
+    what: &'static str,
+    running: usize,
+) -> Result<(), SnapshotError> {
+    let saved = u32::from_le_bytes(read_bytes(reader)?) as usize;
+    if saved != running {
+        return Err(SnapshotError::SetupMismatch {
+            what,
+            saved,
+            running,
+        });
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn save_snapshot_file(world: &FluidWorld, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    write_snapshot(world, &mut writer)?;
+    writer.flush()
+}
+
+pub fn load_snapshot_file(path: &Path, setup: &FluidWorld) -> Result<FluidWorld, SnapshotError> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    read_snapshot(&mut reader, setup)
+}
+
+// F5 quick saves the running simulation, F9 restores the last quick save
+pub fn handle_snapshot_keys(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keys: Res<ButtonInput<KeyCode>>,
+    world: Res<FluidWorld>,
+    particles: Query<Entity, With<Particle>>,
+) {
+    let path = Path::new(QUICK_SAVE_PATH);
+    if keys.just_pressed(SAVE_KEY) {
+        match save_snapshot_file(&world, path) {
+            // hash lets two deterministic runs be compared without diffing files
+            Ok(()) => info!(
+                "saved snapshot to {} (state hash {:016x})",
+                path.display(),
+                world.state_hash()
+            ),
+            Err(err) => error!("can't save snapshot to {}: {}", path.display(), err),
+        }
+    }
+
+    if keys.just_pressed(LOAD_KEY) {
+        let loaded = match load_snapshot_file(path, &world) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("can't load snapshot from {}: {}", path.display(), err);
+                return;
+            }
+        };
+        particles_spawning::respawn_particles(&mut commands, &asset_server, &particles, loaded);
+        info!("loaded snapshot from {}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        flip::TransferKind,
+        integrators::IntegratorKind,
+        scenario::Scenario,
+        sim_config::{SimConfig, SolverKind},
+    };
+
+    const SCENARIO: &str = r#"
+[[fluid]]
+shape = { type = "rect", center = [0.0, -400.0], size = [30.0, 30.0] }
+
+[[emitter]]
+position = [0.0, -300.0]
+velocity = [0.0, -50.0]
+rate = 200.0
+width = 9.0
+
+[[body]]
+shape = { type = "circle", center = [0.0, -350.0], radius = 6.0 }
+relative_density = 0.5
+"#;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let scenario: Scenario = toml::from_str(SCENARIO).unwrap();
+        // apic is the only state that lives in the affine velocities
+        let mut config = SimConfig {
+            solver: SolverKind::Flip,
+            ..SimConfig::default()
+        };
+        config.flip.transfer = TransferKind::Apic;
+        let mut world = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        for _ in 0..5 {
+            world.step(&config, config.physics_step);
+        }
+        assert!(world.emitters[0].emitted > 0);
+        assert!(
+            world
+                .affine_velocities
+                .iter()
+                .any(|affine| *affine != Mat2::ZERO)
+        );
+
+        let mut bytes = Vec::new();
+        write_snapshot(&world, &mut bytes).unwrap();
+        let setup = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        let loaded = read_snapshot(&mut bytes.as_slice(), &setup).unwrap();
+
+        assert_eq!(loaded.len(), world.len());
+        assert_eq!(loaded.state_hash(), world.state_hash());
+        assert_eq!(loaded.steps, world.steps);
+        assert_eq!(loaded.time, world.time);
+        assert_eq!(loaded.affine_velocities, world.affine_velocities);
+        for (loaded, body) in loaded.bodies.iter().zip(&world.bodies) {
+            assert_eq!(loaded.position, body.position);
+            assert_eq!(loaded.angle, body.angle);
+            assert_eq!(loaded.velocity, body.velocity);
+            assert_eq!(loaded.angular_velocity, body.angular_velocity);
+        }
+        for (loaded, emitter) in loaded.emitters.iter().zip(&world.emitters) {
+            assert_eq!(loaded.accumulated, emitter.accumulated);
+            assert_eq!(loaded.emitted, emitter.emitted);
+        }
+    }
+
+    // leapfrog reuses the forces at the end of the last step and ice holds the shape its
+    // cluster formed in, a loaded world needs both to go on like the saved one
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+[[fluid]]
+shape = { type = "rect", center = [0.0, -400.0], size = [60.0, 30.0] }
+temperature = 40.0
+
+[[fluid]]
+shape = { type = "rect", center = [0.0, -360.0], size = [15.0, 15.0] }
+temperature = -20.0
+
+[[body]]
+shape = { type = "circle", center = [20.0, -360.0], radius = 6.0 }
+"#,
+        )
+        .unwrap();
+        let mut config = SimConfig {
+            integrator: IntegratorKind::Leapfrog,
+            ..SimConfig::default()
+        };
+        config.heat.freezing_temperature = Some(0f32);
+        let mut world = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        for _ in 0..10 {
+            world.step(&config, config.physics_step);
+        }
+        assert!(!world.ice_clusters.is_empty());
+        assert!(world.end_accelerations_step.is_some());
+
+        let mut bytes = Vec::new();
+        write_snapshot(&world, &mut bytes).unwrap();
+        let setup = particles_spawning::create_fluid_world_from_scenario(&scenario, &config);
+        let mut loaded = read_snapshot(&mut bytes.as_slice(), &setup).unwrap();
+        for _ in 0..10 {
+            world.step(&config, config.physics_step);
+            loaded.step(&config, config.physics_step);
+        }
+        assert_eq!(loaded.state_hash(), world.state_hash());
+    }
+}
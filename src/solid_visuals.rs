@@ -0,0 +1,27 @@
+use bevy::{
+    color::palettes::css::{LIGHT_GRAY, ORANGE},
+    prelude::*,
+};
+
+use crate::{fluid_world::FluidWorld, obstacles::ObstacleOutlines, sim_config::SimConfig};
+
+const BODY_COLOR: Srgba = ORANGE;
+const OBSTACLE_COLOR: Srgba = LIGHT_GRAY;
+
+// outlines of the rigid bodies, they have no sprites
+pub fn draw_rigid_bodies(world: Res<FluidWorld>, mut gizmos: Gizmos) {
+    for body in &world.bodies {
+        gizmos.linestrip_2d(body.outline(), BODY_COLOR);
+    }
+}
+
+pub fn draw_obstacles(
+    world: Res<FluidWorld>,
+    config: Res<SimConfig>,
+    mut outlines: Local<ObstacleOutlines>,
+    mut gizmos: Gizmos,
+) {
+    for (start, end) in outlines.segments(&world.obstacles, config.box_size) {
+        gizmos.line_2d(*start, *end, OBSTACLE_COLOR);
+    }
+}
@@ -1,5 +1,11 @@
 use bevy::prelude::*;
 
+use crate::{
+    fluid_world::FluidWorld,
+    particle_physics::PhysicsClock,
+    sim_config::{SimConfig, SolverKind},
+};
+
 #[derive(Component)]
 pub struct FpsText;
 pub fn setup_ui(commands: &mut Commands) {
@@ -14,8 +20,34 @@ pub fn setup_ui(commands: &mut Commands) {
         FpsText {},
     ));
 }
-pub fn update_ui(mut fps_text_query: Query<&mut Text, With<FpsText>>, time: Res<Time>) {
+pub fn update_ui(
+    mut fps_text_query: Query<&mut Text, With<FpsText>>,
+    time: Res<Time>,
+    clock: Res<PhysicsClock>,
+    world: Res<FluidWorld>,
+    config: Res<SimConfig>,
+) {
     let fps = (1f32 / time.delta_secs()).round();
     let mut fps_text = fps_text_query.single_mut();
-    fps_text.0 = format!("fps: {}", fps);
+    fps_text.0 = format!(
+        "fps: {}\ndt: {:.5}\nsubsteps: {}",
+        fps, clock.last_step, clock.last_substeps
+    );
+    if matches!(
+        config.solver,
+        SolverKind::Pcisph | SolverKind::Dfsph | SolverKind::Pbf
+    ) {
+        fps_text.0 += &format!(
+            "\npressure iterations: {}\ndensity error: {:.2}%",
+            world.solver_stats.iterations,
+            world.solver_stats.density_error * 100f32
+        );
+    }
+    if matches!(config.solver, SolverKind::Dfsph | SolverKind::Flip) {
+        fps_text.0 += &format!(
+            "\ndivergence iterations: {}\ndivergence error: {:.2}%",
+            world.solver_stats.divergence_iterations,
+            world.solver_stats.divergence_error * 100f32
+        );
+    }
 }